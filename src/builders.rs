@@ -1,77 +1,559 @@
+use diagnostics::{self, Diagnostic};
 use gtk::traits::*;
-use gtk::widgets;
-use std::path::Path;
+use gtk::{signals, widgets};
+use std::cell::Cell;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-pub fn show_builder(state: &mut ::utils::State, build_buttons: &mut widgets::Box, build_terms: &mut widgets::Stack) {
-    let mut should_show = false;
+#[derive(PartialEq)]
+pub enum BuildStatus {
+    Idle,
+    Running,
+    Succeeded,
+    Failed(i32)
+}
+
+pub struct Builder {
+    pub term: widgets::VteTerminal,
+    pub pid: i32,
+    // the last pid forked into the terminal, which is the one vte reports the exit of
+    pub forked_pid: Rc<Cell<i32>>,
+    pub build_id: u64,
+    pub diagnostics: Vec<Diagnostic>,
+    // the cargo process collecting diagnostics, until its reading thread reaps it
+    pub diagnostics_process: Arc<Mutex<Option<Child>>>,
+    pub status: BuildStatus,
+    // a command waiting for the one being stopped to exit, since both would want cargo's lock
+    pub pending_command: Option<String>,
+    pub command: String,
+    pub start_time: i64,
+    pub duration: i64
+}
+
+pub enum BuildEvent {
+    Diagnostic(PathBuf, u64, Diagnostic),
+    DiagnosticsFinished(PathBuf, u64),
+    Exited(PathBuf, i32),
+    StopTimedOut(PathBuf, i32)
+}
+
+pub struct BuildCommand {
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub working_dir: PathBuf
+}
+
+impl BuildCommand {
+    // the terminal can't be given an environment directly, so run it through env
+    fn get_term_args(&self) -> Vec<String> {
+        let mut term_args = Vec::new();
+        if self.env.len() > 0 {
+            term_args.push("env".to_string());
+            for &(ref key, ref value) in self.env.iter() {
+                term_args.push(format!("{}={}", key, value));
+            }
+        }
+        term_args.push_all(self.args.as_ref());
+        term_args
+    }
+}
+
+impl BuildStatus {
+    pub fn icon_name(&self) -> &'static str {
+        match *self {
+            BuildStatus::Idle => "",
+            BuildStatus::Running => "media-playback-start",
+            BuildStatus::Succeeded => "emblem-default",
+            BuildStatus::Failed(_) => "dialog-error"
+        }
+    }
+}
+
+fn get_quickfix_items(state: &::utils::State) -> Vec<::nvim::QuickfixItem> {
+    let mut items = Vec::new();
+    if let Some(ref project_path) = state.visible_builder {
+        if let Some(builder) = state.builders.get(project_path) {
+            for diag in builder.diagnostics.iter() {
+                if let Some(span) = diag.primary_span() {
+                    items.push(::nvim::QuickfixItem {
+                        file_name: span.file_name.clone(),
+                        line: span.line_start,
+                        column: span.column_start,
+                        is_error: diag.level == "error",
+                        text: diag.summary()
+                    });
+                }
+            }
+        }
+    }
+    items
+}
+
+fn get_profiles(state: &::utils::State, project_path: &Path) -> Vec<::utils::ProfileSettings> {
+    ::utils::get_project_settings(state, project_path).builds
+        .and_then(|builds| builds.profiles).unwrap_or(Vec::new())
+}
+
+fn get_active_profile(state: &::utils::State, project_path: &Path) -> Option<::utils::ProfileSettings> {
+    let profiles = get_profiles(state, project_path);
+    if let Some(project_str) = project_path.to_str() {
+        if let Some(name) = state.build_profiles.get(project_str) {
+            if let Some(profile) = profiles.iter().find(|profile| profile.name == *name) {
+                return Some(profile.clone());
+            }
+        }
+    }
+    profiles.into_iter().next()
+}
+
+fn get_command(state: &::utils::State, project_path: &Path, name: &str) -> Option<BuildCommand> {
+    let project_settings = ::utils::get_project_settings(state, project_path);
+    let builds = match project_settings.builds {
+        Some(builds) => builds,
+        None => return None
+    };
+    let command = match name {
+        "run" => builds.run,
+        "build" => builds.build,
+        "test" => builds.test,
+        "clean" => builds.clean,
+        _ => None
+    };
+
+    command.map(|command| {
+        let mut args = command.args.clone();
+        let mut env = Vec::new();
+
+        if let Some(ref project_env) = project_settings.env {
+            for (key, value) in project_env.iter() {
+                env.push((key.clone(), value.clone()));
+            }
+        }
+
+        if command.use_profile.unwrap_or(true) {
+            if let Some(profile) = get_active_profile(state, project_path) {
+                let mut profile_args = Vec::new();
+                if profile.release.unwrap_or(false) && !args.contains(&"--release".to_string()) {
+                    profile_args.push("--release".to_string());
+                }
+                if let Some(ref features) = profile.features {
+                    if features.len() > 0 {
+                        profile_args.push("--features".to_string());
+                        profile_args.push(features.connect(" "));
+                    }
+                }
+                if let Some(ref target) = profile.target {
+                    profile_args.push("--target".to_string());
+                    profile_args.push(target.clone());
+                }
+                if let Some(ref extra_args) = profile.extra_args {
+                    profile_args.push_all(extra_args.as_ref());
+                }
+
+                // anything after -- is passed to the program, so the profile goes before it
+                let split = args.iter().position(|arg| *arg == "--").unwrap_or(args.len());
+                let tail = args.split_off(split);
+                args.push_all(profile_args.as_ref());
+                args.push_all(tail.as_ref());
+
+                if let Some(ref profile_env) = profile.env {
+                    for (key, value) in profile_env.iter() {
+                        env.retain(|&(ref k, _)| k != key);
+                        env.push((key.clone(), value.clone()));
+                    }
+                }
+            }
+        }
+
+        if let Some(ref command_env) = command.env {
+            for (key, value) in command_env.iter() {
+                env.retain(|&(ref k, _)| k != key);
+                env.push((key.clone(), value.clone()));
+            }
+        }
+
+        BuildCommand {
+            args: args,
+            env: env,
+            working_dir: match command.working_dir {
+                Some(ref dir) => project_path.join(dir),
+                None => project_path.to_path_buf()
+            }
+        }
+    })
+}
+
+pub fn update_profiles(state: &mut ::utils::State) {
+    state.is_refreshing_profiles = true;
+
+    state.profile_combo.remove_all();
+    if let Some(project_path) = state.visible_builder.clone() {
+        let active_name = get_active_profile(state, &project_path).map(|profile| profile.name);
+        for (i, profile) in get_profiles(state, &project_path).iter().enumerate() {
+            state.profile_combo.append_text(profile.name.as_ref());
+            if Some(profile.name.clone()) == active_name {
+                state.profile_combo.set_active(i as i32);
+            }
+        }
+    }
+
+    state.is_refreshing_profiles = false;
+}
+
+pub fn select_profile(state: &mut ::utils::State) {
+    if !state.is_refreshing_profiles {
+        if let Some(project_path) = state.visible_builder.clone() {
+            if let Some(project_str) = project_path.to_str() {
+                if let Some(name) = state.profile_combo.get_active_text() {
+                    state.build_profiles.insert(project_str.to_string(), name);
+                    ::utils::write_prefs(state);
+                }
+            }
+        }
+    }
+}
+
+pub fn update_quickfix(state: &::utils::State, client: &mut ::nvim::Client) {
+    let items = get_quickfix_items(state);
+    client.set_quickfix_list(&items);
+    client.set_signs(&items);
+}
+
+pub fn update_signs(state: &::utils::State, client: &mut ::nvim::Client, path_str: &str) {
+    let items = get_quickfix_items(state);
+    client.add_signs_for_file(&items, path_str);
+}
+
+pub fn show_builder(
+    state: &mut ::utils::State,
+    client: &mut ::nvim::Client,
+    build_buttons: &mut widgets::Box,
+    build_terms: &mut widgets::Stack)
+{
+    let mut visible_builder = None;
 
     if let Some(ref path_str) = state.selection {
         if let Some(ref project_path) = ::utils::get_project_path(state, Path::new(path_str)) {
             if ::utils::is_project_root(state, project_path) {
                 if !state.builders.contains_key(project_path) {
                     let term = widgets::VteTerminal::new().unwrap();
+                    let notifier = state.build_notifier.clone();
+                    let exited_path = project_path.clone();
+                    let forked_pid = Rc::new(Cell::new(-1));
+                    let exited_pid = forked_pid.clone();
+                    term.connect(signals::ChildExited::new(&mut move || {
+                        notifier.send(BuildEvent::Exited(exited_path.clone(), exited_pid.get()));
+                    }));
                     term.show_all();
                     build_terms.add(&term);
-                    state.builders.insert(project_path.clone(), (term, -1));
+                    state.builders.insert(project_path.clone(), Builder {
+                        term: term,
+                        pid: -1,
+                        forked_pid: forked_pid,
+                        build_id: 0,
+                        diagnostics: Vec::new(),
+                        diagnostics_process: Arc::new(Mutex::new(None)),
+                        status: BuildStatus::Idle,
+                        pending_command: None,
+                        command: String::new(),
+                        start_time: 0,
+                        duration: 0
+                    });
                 }
-                if let Some(&(ref term, _)) = state.builders.get(project_path) {
-                    build_terms.set_visible_child(term);
-                    should_show = true;
+                if let Some(builder) = state.builders.get(project_path) {
+                    build_terms.set_visible_child(&builder.term);
+                    visible_builder = Some(project_path.clone());
                 }
             }
         }
     }
 
-    build_buttons.set_sensitive(should_show);
-    if should_show {
+    build_buttons.set_sensitive(visible_builder.is_some());
+    if visible_builder.is_some() {
         build_terms.show_all();
     } else {
         build_terms.hide();
     }
+
+    if state.visible_builder != visible_builder {
+        state.visible_builder = visible_builder;
+        ::ui::update_diagnostics(state);
+        update_profiles(state);
+        update_quickfix(state, client);
+    }
 }
 
-pub fn run_builder(state: &mut ::utils::State, args: &[&str]) {
-    if let Some(project_path) = ::utils::get_selected_project_path(state) {
-        if let Some(project_path_str) = project_path.to_str() {
-            if let Some(&mut(ref mut term, ref mut current_pid)) = state.builders.get_mut(&project_path) {
-                match term.fork_command(project_path_str.as_ref(), args) {
-                    Ok(pid) => { *current_pid = pid },
-                    Err(s) => {
-                        term.feed(s.as_ref());
-                        term.feed("\r\n");
+// the child stays in the mutex until it has been waited on, so killing it can't hit a reused pid
+fn run_diagnostics(
+    notifier: ::ffi::Notifier<BuildEvent>,
+    project_path: PathBuf,
+    build_id: u64,
+    args: Vec<String>,
+    command: &BuildCommand) -> Arc<Mutex<Option<Child>>>
+{
+    let mut process = Command::new(&args[0]);
+    process.args(&args[1..]).current_dir(&command.working_dir)
+        .stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::null());
+    for &(ref key, ref value) in command.env.iter() {
+        process.env(key, value);
+    }
+
+    let mut child = match process.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            println!("Error collecting diagnostics: {}", e);
+            notifier.send(BuildEvent::DiagnosticsFinished(project_path, build_id));
+            return Arc::new(Mutex::new(None));
+        }
+    };
+    let stdout = child.stdout.take();
+    let diagnostics_process = Arc::new(Mutex::new(Some(child)));
+    let thread_process = diagnostics_process.clone();
+
+    thread::spawn(move || {
+        if let Some(stdout) = stdout {
+            for line in BufReader::new(stdout).lines() {
+                if let Ok(line_str) = line {
+                    if let Some(diag) = diagnostics::parse_message(&project_path, line_str.as_ref()) {
+                        notifier.send(BuildEvent::Diagnostic(project_path.clone(), build_id, diag));
                     }
                 }
             }
         }
+        if let Ok(mut process) = thread_process.lock() {
+            if let Some(mut child) = process.take() {
+                child.wait().ok();
+            }
+        }
+        notifier.send(BuildEvent::DiagnosticsFinished(project_path, build_id));
+    });
+
+    diagnostics_process
+}
+
+fn kill_diagnostics(builder: &Builder) {
+    if let Ok(mut process) = builder.diagnostics_process.lock() {
+        if let Some(ref mut child) = *process {
+            child.kill().ok();
+        }
+    }
+}
+
+fn start_command(state: &mut ::utils::State, client: &mut ::nvim::Client, project_path: &Path, name: &str) {
+    let command = match get_command(state, project_path, name) {
+        Some(command) => command,
+        None => {
+            println!("No {} command is configured", name);
+            return;
+        }
+    };
+    let use_clippy = ::utils::get_project_settings(state, project_path).clippy.unwrap_or(false);
+    if let Some(working_dir_str) = command.working_dir.to_str() {
+        if let Some(builder) = state.builders.get_mut(project_path) {
+            let term_args = command.get_term_args();
+            let term_args_ref: Vec<&str> = term_args.iter().map(|arg| arg.as_ref()).collect();
+            builder.command = command.args.connect(" ");
+            builder.start_time = ::ffi::now_ms();
+            builder.duration = 0;
+            match builder.term.fork_command(working_dir_str, term_args_ref.as_ref()) {
+                Ok(pid) => {
+                    builder.pid = pid;
+                    builder.forked_pid.set(pid);
+                    builder.status = BuildStatus::Running;
+                },
+                Err(s) => {
+                    builder.term.feed(s.as_ref());
+                    builder.term.feed("\r\n");
+                    builder.status = BuildStatus::Failed(-1);
+                }
+            }
+
+            kill_diagnostics(builder);
+            builder.build_id += 1;
+            builder.diagnostics.clear();
+            let args_ref: Vec<&str> = command.args.iter().map(|arg| arg.as_ref()).collect();
+            if let Some(json_args) = diagnostics::get_json_args(args_ref.as_ref(), use_clippy) {
+                builder.diagnostics_process = run_diagnostics(state.build_notifier.clone(), project_path.to_path_buf(),
+                                                              builder.build_id, json_args, &command);
+            }
+        }
+    }
+    ::ui::update_diagnostics(state);
+    ::ui::update_build_status(state);
+    update_quickfix(state, client);
+}
+
+// if the builder is still running, the command is started once the old one has exited
+pub fn run_builder(state: &mut ::utils::State, client: &mut ::nvim::Client, name: &str) {
+    if let Some(project_path) = ::utils::get_selected_project_path(state) {
+        let mut is_running = false;
+        if let Some(builder) = state.builders.get_mut(&project_path) {
+            if builder.pid >= 0 {
+                builder.pending_command = Some(name.to_string());
+                is_running = true;
+            }
+        }
+        if is_running {
+            stop_process(state, &project_path);
+        } else {
+            start_command(state, client, &project_path, name);
+        }
+    }
+}
+
+fn finish_process(builder: &mut Builder) {
+    let exit_status = builder.term.get_child_exit_status();
+    builder.pid = -1;
+    builder.duration = ::ffi::now_ms() - builder.start_time;
+    builder.status = if exit_status == 0 {
+        BuildStatus::Succeeded
+    } else {
+        BuildStatus::Failed(exit_status)
+    };
+    let msg = format!("==={} finished with exit code {} after {:.1}s===\r\n",
+                      builder.command, exit_status, (builder.duration as f64) / 1000.0);
+    builder.term.feed(msg.as_ref());
+}
+
+pub fn handle_event(state: &mut ::utils::State, client: &mut ::nvim::Client, event: BuildEvent) {
+    let (project_path, build_id, is_finished) = match event {
+        BuildEvent::Diagnostic(ref project_path, build_id, _) => (project_path.clone(), build_id, false),
+        BuildEvent::DiagnosticsFinished(ref project_path, build_id) => (project_path.clone(), build_id, true),
+        BuildEvent::Exited(ref project_path, pid) => {
+            // a late exit from a stopped child must not finish the build that replaced it,
+            // so the process it names has to be the current one and actually be gone
+            let mut pending_command = None;
+            if let Some(builder) = state.builders.get_mut(project_path) {
+                if builder.pid >= 0 && pid == builder.pid && !::ffi::is_process_alive(pid) {
+                    finish_process(builder);
+                    pending_command = builder.pending_command.take();
+                }
+            }
+            ::ui::update_build_status(state);
+            if let Some(name) = pending_command {
+                start_command(state, client, project_path, name.as_ref());
+            }
+            return;
+        },
+        BuildEvent::StopTimedOut(ref project_path, pid) => {
+            // the pid could belong to something else by now unless the builder still has it
+            let is_current = state.builders.get(project_path).map(|builder| builder.pid == pid).unwrap_or(false);
+            if is_current && ::ffi::is_process_group_alive(pid) {
+                ::ffi::signal_process_group(pid, ::ffi::SIGKILL);
+            }
+            return;
+        }
+    };
+
+    if let Some(builder) = state.builders.get_mut(&project_path) {
+        // ignore anything left over from an earlier build
+        if builder.build_id != build_id {
+            return;
+        }
+        if let BuildEvent::Diagnostic(_, _, diag) = event {
+            builder.diagnostics.push(diag);
+        }
+    }
+
+    if state.visible_builder == Some(project_path) {
+        ::ui::update_diagnostics(state);
+        if is_finished {
+            update_quickfix(state, client);
+        }
+    }
+}
+
+pub fn open_diagnostic(state: &mut ::utils::State, client: &mut ::nvim::Client) {
+    let mut iter = widgets::TreeIter::new().unwrap();
+
+    if state.diagnostics_selection.get_selected(state.diagnostics_model, &mut iter) {
+        if let Some(path_str) = state.diagnostics_model.get_value(&iter, 1).get_string() {
+            let line = state.diagnostics_model.get_value(&iter, 2).get_string()
+                .and_then(|s| s.parse::<u64>().ok()).unwrap_or(1);
+            let column = state.diagnostics_model.get_value(&iter, 3).get_string()
+                .and_then(|s| s.parse::<u64>().ok()).unwrap_or(1);
+            client.edit(path_str.as_ref());
+            client.set_cursor(line, column);
+        }
     }
 }
 
-fn stop_process(term: &mut widgets::VteTerminal, current_pid: &mut i32) {
-    if *current_pid >= 0 {
-        ::ffi::kill_process(*current_pid);
-        term.feed("===Finished===\r\n");
-        *current_pid = -1;
+fn get_stop_signal(state: &::utils::State) -> i32 {
+    match state.settings.stop_signal {
+        Some(ref name) => ::ffi::get_signal(name.as_ref()),
+        None => ::ffi::SIGINT
+    }
+}
+
+fn get_grace_period(state: &::utils::State) -> u32 {
+    state.settings.stop_grace_period.unwrap_or(0)
+}
+
+// the pid is reset once the child-exited signal arrives, so it's never stale here
+fn stop_process(state: &mut ::utils::State, project_path: &Path) {
+    let signal = get_stop_signal(state);
+    let grace_period = get_grace_period(state);
+
+    if let Some(builder) = state.builders.get(project_path) {
+        kill_diagnostics(builder);
+        if builder.pid >= 0 {
+            ::ffi::signal_process_group(builder.pid, signal);
+
+            // escalate to SIGKILL if it's still running once the grace period is over
+            let notifier = state.build_notifier.clone();
+            let timed_out_path = project_path.to_path_buf();
+            let pid = builder.pid;
+            thread::spawn(move || {
+                thread::sleep_ms(grace_period);
+                notifier.send(BuildEvent::StopTimedOut(timed_out_path, pid));
+            });
+        }
     }
 }
 
 pub fn stop_builder(state: &mut ::utils::State) {
     if let Some(project_path) = ::utils::get_selected_project_path(&state) {
-        if let Some(&mut(ref mut term, ref mut current_pid)) = state.builders.get_mut(&project_path) {
-            stop_process(term, current_pid);
+        if let Some(builder) = state.builders.get_mut(&project_path) {
+            builder.pending_command = None;
         }
+        stop_process(state, &project_path);
     }
 }
 
+// blocks until every builder has exited, since the main loop is no longer running
 pub fn stop_builders(state: &mut ::utils::State) {
-    for (_, mut builder) in state.builders.iter_mut() {
-        let (ref mut term, ref mut current_pid) : (widgets::VteTerminal, i32) = *builder;
-        stop_process(term, current_pid);
+    let signal = get_stop_signal(state);
+    let deadline = ::ffi::now_ms() + get_grace_period(state) as i64;
+
+    let mut pids = Vec::new();
+    for (_, builder) in state.builders.iter() {
+        kill_diagnostics(builder);
+        if builder.pid >= 0 && ::ffi::signal_process_group(builder.pid, signal) {
+            pids.push(builder.pid);
+        }
+    }
+
+    while pids.len() > 0 {
+        for pid in pids.iter() {
+            ::ffi::reap_process(*pid);
+        }
+        pids.retain(|pid| ::ffi::is_process_group_alive(*pid));
+        if ::ffi::now_ms() >= deadline {
+            for pid in pids.iter() {
+                ::ffi::signal_process_group(*pid, ::ffi::SIGKILL);
+            }
+            break;
+        }
+        thread::sleep_ms(50);
     }
 }
 
 pub fn set_builders_font_size(state: &mut ::utils::State) {
-    for (_, mut builder) in state.builders.iter_mut() {
-        let (ref mut term, _) : (widgets::VteTerminal, i32) = *builder;
-        term.set_font_size(state.font_size);
+    for (_, builder) in state.builders.iter_mut() {
+        builder.term.set_font_size(state.font_size);
     }
 }
@@ -0,0 +1,207 @@
+use rustc_serialize::json::Json;
+use std::path::Path;
+
+pub struct Span {
+    pub file_name: String,
+    pub line_start: u64,
+    pub line_end: u64,
+    pub column_start: u64,
+    pub column_end: u64,
+    pub is_primary: bool,
+    pub label: Option<String>
+}
+
+pub struct Diagnostic {
+    pub level: String,
+    pub code: Option<String>,
+    pub message: String,
+    pub spans: Vec<Span>,
+    pub suggestions: Vec<String>
+}
+
+impl Diagnostic {
+    pub fn primary_span(&self) -> Option<&Span> {
+        self.spans.iter().find(|span| span.is_primary).or(self.spans.first())
+    }
+
+    pub fn summary(&self) -> String {
+        match self.code {
+            Some(ref code) => format!("{}[{}]: {}", self.level, code, self.message),
+            None => format!("{}: {}", self.level, self.message)
+        }
+    }
+}
+
+// returns the cargo arguments that build the same thing while emitting json messages
+pub fn get_json_args(args: &[&str], use_clippy: bool) -> Option<Vec<String>> {
+    if args.len() < 2 || args[0] != "cargo" {
+        return None;
+    }
+
+    let mut json_args = vec![args[0].to_string()];
+    match args[1] {
+        "build" | "run" if use_clippy => json_args.push("clippy".to_string()),
+        "build" | "run" => json_args.push("build".to_string()),
+        "test" => json_args.push_all(&["test".to_string(), "--no-run".to_string()]),
+        _ => return None
+    }
+    for arg in args[2..].iter() {
+        // anything after -- belongs to the program being run
+        if *arg == "--" {
+            break;
+        }
+        json_args.push(arg.to_string());
+    }
+    json_args.push("--message-format=json".to_string());
+    Some(json_args)
+}
+
+fn get_string(json: &Json, key: &str) -> Option<String> {
+    json.find(key).and_then(|value| value.as_string()).map(|s| s.to_string())
+}
+
+fn get_u64(json: &Json, key: &str) -> u64 {
+    json.find(key).and_then(|value| value.as_u64()).unwrap_or(0)
+}
+
+fn parse_span(project_path: &Path, json: &Json) -> Option<Span> {
+    if let Some(file_name) = get_string(json, "file_name") {
+        if let Some(file_str) = project_path.join(&file_name).to_str() {
+            return Some(Span {
+                file_name: file_str.to_string(),
+                line_start: get_u64(json, "line_start"),
+                line_end: get_u64(json, "line_end"),
+                column_start: get_u64(json, "column_start"),
+                column_end: get_u64(json, "column_end"),
+                is_primary: json.find("is_primary").and_then(|v| v.as_boolean()).unwrap_or(false),
+                label: get_string(json, "label")
+            });
+        }
+    }
+    None
+}
+
+fn parse_suggestions(json: &Json) -> Vec<String> {
+    let mut suggestions = Vec::new();
+    if let Some(children) = json.find("children").and_then(|v| v.as_array()) {
+        for child in children.iter() {
+            if let Some(message) = get_string(child, "message") {
+                let mut replacements = Vec::new();
+                if let Some(spans) = child.find("spans").and_then(|v| v.as_array()) {
+                    for span in spans.iter() {
+                        if let Some(replacement) = get_string(span, "suggested_replacement") {
+                            replacements.push(replacement);
+                        }
+                    }
+                }
+                let level = get_string(child, "level").unwrap_or("note".to_string());
+                if replacements.is_empty() {
+                    suggestions.push(format!("{}: {}", level, message));
+                } else {
+                    suggestions.push(format!("{}: {} `{}`", level, message, replacements.connect("`, `")));
+                }
+            }
+        }
+    }
+    suggestions
+}
+
+// parses one line of cargo's json output, ignoring anything that isn't a compiler message with a location
+pub fn parse_message(project_path: &Path, line: &str) -> Option<Diagnostic> {
+    let json = match Json::from_str(line) {
+        Ok(json) => json,
+        Err(_) => return None
+    };
+    if get_string(&json, "reason") != Some("compiler-message".to_string()) {
+        return None;
+    }
+
+    if let Some(message) = json.find("message") {
+        let mut spans = Vec::new();
+        if let Some(spans_json) = message.find("spans").and_then(|v| v.as_array()) {
+            for span_json in spans_json.iter() {
+                if let Some(span) = parse_span(project_path, span_json) {
+                    spans.push(span);
+                }
+            }
+        }
+        if spans.is_empty() {
+            return None;
+        }
+
+        return Some(Diagnostic {
+            level: get_string(message, "level").unwrap_or("error".to_string()),
+            code: message.find("code").and_then(|code| get_string(code, "code")),
+            message: get_string(message, "message").unwrap_or(String::new()),
+            spans: spans,
+            suggestions: parse_suggestions(message)
+        });
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_json_args, parse_message};
+    use std::path::Path;
+
+    #[test]
+    fn json_args_for_build_and_run() {
+        assert_eq!(get_json_args(&["cargo", "build", "--release"], false),
+                   Some(vec!["cargo".to_string(), "build".to_string(), "--release".to_string(),
+                             "--message-format=json".to_string()]));
+        assert_eq!(get_json_args(&["cargo", "run", "--release", "--", "--port", "80"], false),
+                   Some(vec!["cargo".to_string(), "build".to_string(), "--release".to_string(),
+                             "--message-format=json".to_string()]));
+    }
+
+    #[test]
+    fn json_args_for_clippy_and_test() {
+        assert_eq!(get_json_args(&["cargo", "run"], true),
+                   Some(vec!["cargo".to_string(), "clippy".to_string(), "--message-format=json".to_string()]));
+        assert_eq!(get_json_args(&["cargo", "test", "--", "--nocapture"], true),
+                   Some(vec!["cargo".to_string(), "test".to_string(), "--no-run".to_string(),
+                             "--message-format=json".to_string()]));
+    }
+
+    #[test]
+    fn no_json_args_for_other_commands() {
+        assert_eq!(get_json_args(&["cargo", "clean"], false), None);
+        assert_eq!(get_json_args(&["make", "build"], false), None);
+        assert_eq!(get_json_args(&["cargo"], false), None);
+    }
+
+    #[test]
+    fn parses_compiler_message() {
+        let line = r#"{"reason":"compiler-message","message":{"level":"error","code":{"code":"E0308"},
+            "message":"mismatched types","spans":[
+                {"file_name":"src/lib.rs","line_start":3,"line_end":3,"column_start":5,"column_end":9,
+                 "is_primary":false,"label":null},
+                {"file_name":"src/main.rs","line_start":10,"line_end":11,"column_start":2,"column_end":8,
+                 "is_primary":true,"label":"expected i32"}],
+            "children":[{"level":"help","message":"try","spans":[{"suggested_replacement":"x as i32"}]},
+                        {"level":"note","message":"see the docs","spans":[]}]}}"#;
+        let diag = parse_message(Path::new("/project"), line).unwrap();
+        assert_eq!(diag.level, "error");
+        assert_eq!(diag.code, Some("E0308".to_string()));
+        assert_eq!(diag.summary(), "error[E0308]: mismatched types");
+        assert_eq!(diag.spans.len(), 2);
+
+        let span = diag.primary_span().unwrap();
+        assert_eq!(span.file_name, "/project/src/main.rs");
+        assert_eq!((span.line_start, span.line_end, span.column_start, span.column_end), (10, 11, 2, 8));
+        assert_eq!(span.label, Some("expected i32".to_string()));
+
+        assert_eq!(diag.suggestions, vec!["help: try `x as i32`".to_string(), "note: see the docs".to_string()]);
+    }
+
+    #[test]
+    fn ignores_messages_without_locations() {
+        let no_spans = r#"{"reason":"compiler-message","message":{"level":"warning","message":"unused","spans":[]}}"#;
+        assert!(parse_message(Path::new("/project"), no_spans).is_none());
+        let artifact = r#"{"reason":"compiler-artifact","filenames":[]}"#;
+        assert!(parse_message(Path::new("/project"), artifact).is_none());
+        assert!(parse_message(Path::new("/project"), "   Compiling foo v0.1.0").is_none());
+    }
+}
@@ -1,13 +1,69 @@
-use libc::{c_int, c_uchar, c_void};
+use libc::{c_int, c_long, c_uchar, c_uint, c_void};
 use libc::consts::os::extra::O_NONBLOCK;
 use libc::consts::os::posix01::F_SETFL;
 use libc::funcs::posix88::fcntl::fcntl;
 use libc::funcs::posix88::unistd::{close, pipe, read, write};
 use libc::types::os::arch::c95::size_t;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+static POLLIN : i16 = 1;
+static CLOCK_MONOTONIC : c_int = 1;
+
+pub static SIGINT : c_int = 2;
+pub static SIGKILL : c_int = 9;
+pub static SIGTERM : c_int = 15;
+static WNOHANG : c_int = 1;
+static EINTR : c_int = 4;
+#[cfg(target_os = "macos")]
+static EAGAIN : c_int = 35;
+#[cfg(not(target_os = "macos"))]
+static EAGAIN : c_int = 11;
+
+static G_PRIORITY_DEFAULT : c_int = 0;
+static G_IO_IN : c_uint = 1;
+static G_IO_ERR : c_uint = 8;
+static G_IO_HUP : c_uint = 16;
+
+static REQUEST : i64 = 0;
+static RESPONSE : i64 = 1;
+static NOTIFICATION : i64 = 2;
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: i16,
+    revents: i16
+}
+
+#[repr(C)]
+struct TimeSpec {
+    tv_sec: c_long,
+    tv_nsec: c_long
+}
 
 extern "C" {
     fn fork () -> c_int;
     fn kill (pid: c_int, sig: c_int) -> c_int;
+    fn waitpid (pid: c_int, status: *mut c_int, options: c_int) -> c_int;
+    fn poll (fds: *mut PollFd, nfds: u64, timeout: c_int) -> c_int;
+    fn clock_gettime (clock_id: c_int, tp: *mut TimeSpec) -> c_int;
+}
+
+#[link(name = "glib-2.0")]
+extern "C" {
+    fn g_unix_fd_add_full (
+        priority: c_int,
+        fd: c_int,
+        condition: c_uint,
+        function: extern "C" fn(c_int, c_uint, *mut c_void) -> c_int,
+        user_data: *mut c_void,
+        notify: extern "C" fn(*mut c_void)
+    ) -> c_uint;
+    fn g_source_remove (tag: c_uint) -> c_int;
 }
 
 pub fn new_pipe() -> [c_int; 2] {
@@ -20,8 +76,36 @@ pub fn fork_process() -> c_int {
     unsafe { fork() }
 }
 
-pub fn kill_process(pid: c_int) -> c_int {
-    unsafe { kill(pid, 9) }
+// builders run in their own process group (vte makes them session leaders),
+// so the group id is the same as the pid of the process that was forked
+pub fn signal_process_group(pgid: c_int, sig: c_int) -> bool {
+    unsafe { kill(-pgid, sig) == 0 }
+}
+
+pub fn is_process_group_alive(pgid: c_int) -> bool {
+    unsafe { kill(-pgid, 0) == 0 }
+}
+
+pub fn is_process_alive(pid: c_int) -> bool {
+    unsafe { kill(pid, 0) == 0 }
+}
+
+// only for use once the main loop has stopped, because vte normally reaps its own children
+pub fn reap_process(pid: c_int) {
+    let mut status : c_int = 0;
+    unsafe { waitpid(pid, &mut status, WNOHANG) };
+}
+
+pub fn get_signal(name: &str) -> c_int {
+    match name {
+        "SIGKILL" => SIGKILL,
+        "SIGTERM" => SIGTERM,
+        _ => SIGINT
+    }
+}
+
+pub fn read_bytes(fd: c_int, buf: &mut [u8]) -> isize {
+    unsafe { read(fd, buf.as_mut_ptr() as *mut c_void, buf.len() as size_t) as isize }
 }
 
 pub fn set_non_blocking(fd: c_int) {
@@ -32,24 +116,310 @@ pub fn close_fd(fd: c_int) {
     unsafe { close(fd) };
 }
 
-pub fn send_message(fd: c_int, command: &str) {
-    let mut arr = ::neovim::Array::new();
-    arr.add_string(command);
-    let msg = ::neovim::serialize_message(1, "vim_command", &arr);
-    let msg_ref: &str = msg.as_ref();
-    let msg_ptr = msg_ref.as_ptr() as *const c_void;
-    unsafe { write(fd, msg_ptr, msg.len() as size_t) };
+pub type FdCallback<'a> = Box<FnMut() -> bool + 'a>;
+
+extern "C" fn fd_watch_trampoline(_: c_int, _: c_uint, user_data: *mut c_void) -> c_int {
+    let callback: &mut FdCallback = unsafe { mem::transmute(user_data) };
+    if callback() { 1 } else { 0 }
+}
+
+extern "C" fn fd_watch_destroy(user_data: *mut c_void) {
+    let _: Box<FdCallback> = unsafe { mem::transmute(user_data) };
 }
 
-pub fn recv_message(fd: c_int) -> Option<::neovim::Array> {
-    let mut buf : [c_uchar; 1024] = [0; 1024];
-    let n = unsafe { read(fd, buf.as_mut_ptr() as *mut c_void, 1024) };
-    if n < 0 {
-        return None;
-    }
+// runs the callback from the main loop whenever the fd is readable or hung up,
+// until the callback returns false or the watch is removed
+pub fn add_fd_watch<'a>(fd: c_int, callback: FdCallback<'a>) -> c_uint {
+    let user_data: *mut c_void = unsafe { mem::transmute(Box::new(callback)) };
     unsafe {
-        let v = Vec::from_raw_buf(buf.as_ptr(), n as usize);
-        let s = String::from_utf8_unchecked(v);
-        Some(::neovim::deserialize_message(&s))
+        g_unix_fd_add_full(
+            G_PRIORITY_DEFAULT,
+            fd,
+            G_IO_IN | G_IO_HUP | G_IO_ERR,
+            fd_watch_trampoline,
+            user_data,
+            fd_watch_destroy
+        )
+    }
+}
+
+pub fn remove_fd_watch(watch_id: c_uint) {
+    unsafe { g_source_remove(watch_id) };
+}
+
+// sends values to the main loop from other threads, waking up a watch on the returned fd
+pub struct Notifier<T> {
+    sender: Sender<T>,
+    write_fd: c_int
+}
+
+impl<T: Send> Notifier<T> {
+    pub fn send(&self, value: T) {
+        if self.sender.send(value).is_ok() {
+            let byte : [c_uchar; 1] = [0];
+            unsafe { write(self.write_fd, byte.as_ptr() as *const c_void, 1) };
+        }
+    }
+}
+
+impl<T: Send> Clone for Notifier<T> {
+    fn clone(&self) -> Notifier<T> {
+        Notifier { sender: self.sender.clone(), write_fd: self.write_fd }
+    }
+}
+
+pub fn new_notifier<T: Send>() -> (Notifier<T>, Receiver<T>, c_int) {
+    let (sender, receiver) = mpsc::channel();
+    let fds = new_pipe();
+    set_non_blocking(fds[0]);
+    (Notifier { sender: sender, write_fd: fds[1] }, receiver, fds[0])
+}
+
+// throws away the wake-up bytes written by a notifier
+pub fn drain_fd(fd: c_int) {
+    let mut buf : [c_uchar; 256] = [0; 256];
+    while unsafe { read(fd, buf.as_mut_ptr() as *mut c_void, 256) } > 0 {}
+}
+
+pub fn wait_readable(fd: c_int, timeout_ms: i32) -> bool {
+    let mut poll_fd = PollFd { fd: fd, events: POLLIN, revents: 0 };
+    unsafe { poll(&mut poll_fd, 1, timeout_ms) > 0 }
+}
+
+pub fn now_ms() -> i64 {
+    let mut ts = TimeSpec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { clock_gettime(CLOCK_MONOTONIC, &mut ts) };
+    (ts.tv_sec as i64) * 1000 + (ts.tv_nsec as i64) / 1000000
+}
+
+fn write_all(fd: c_int, msg: &str) {
+    let bytes = msg.as_bytes();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let ptr = bytes[offset..].as_ptr() as *const c_void;
+        let n = unsafe { write(fd, ptr, (bytes.len() - offset) as size_t) };
+        if n <= 0 {
+            println!("Error writing message to nvim");
+            return;
+        }
+        offset += n as usize;
+    }
+}
+
+// reads everything currently available, returning false if the other end was closed
+fn read_available(fd: c_int, decoder: &mut ::msgpack::Decoder) -> bool {
+    let mut buf : [c_uchar; 4096] = [0; 4096];
+    loop {
+        let n = unsafe { read(fd, buf.as_mut_ptr() as *mut c_void, 4096) };
+        if n > 0 {
+            decoder.feed(&buf[..n as usize]);
+        } else if n == 0 {
+            return false;
+        } else {
+            match io::Error::last_os_error().raw_os_error() {
+                Some(e) if e == EINTR => continue,
+                Some(e) if e == EAGAIN => return true,
+                _ => return false
+            }
+        }
+    }
+}
+
+// the msgpack decoder takes the raw message in a String, and the strings inside it are made valid first
+fn deserialize_message(msg: Vec<u8>) -> ::neovim::Array {
+    let s = unsafe { String::from_utf8_unchecked(::msgpack::sanitize_strings(msg.as_ref())) };
+    ::neovim::deserialize_message(&s)
+}
+
+pub enum RpcError {
+    Remote(String),
+    Timeout,
+    Disconnected
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RpcError::Remote(ref msg) => write!(f, "{}", msg),
+            RpcError::Timeout => write!(f, "timed out waiting for nvim"),
+            RpcError::Disconnected => write!(f, "nvim is not connected")
+        }
+    }
+}
+
+pub type RpcResult = Result<::neovim::Object, RpcError>;
+pub type RpcCallback = Box<FnMut(RpcResult)>;
+
+enum Pending {
+    Ignore,
+    Wait,
+    Callback(RpcCallback)
+}
+
+pub struct RpcClient {
+    read_fd: c_int,
+    write_fd: c_int,
+    next_id: u64,
+    pending: HashMap<u64, Pending>,
+    responses: HashMap<u64, RpcResult>,
+    notifications: VecDeque<::neovim::Array>,
+    decoder: ::msgpack::Decoder,
+    is_connected: bool,
+    wake_fds: [c_int; 2]
+}
+
+fn get_error(err: ::neovim::Object) -> Option<RpcError> {
+    match err {
+        ::neovim::Object::Nil => None,
+        ::neovim::Object::Array(err_arr) => {
+            // nvim sends errors as [type, message]
+            if let Some(::neovim::Object::String(msg)) = err_arr.get(1) {
+                Some(RpcError::Remote(msg))
+            } else {
+                Some(RpcError::Remote("unknown error".to_string()))
+            }
+        },
+        ::neovim::Object::String(msg) => Some(RpcError::Remote(msg)),
+        _ => Some(RpcError::Remote("unknown error".to_string()))
+    }
+}
+
+impl RpcClient {
+    pub fn new(read_fd: c_int, write_fd: c_int) -> RpcClient {
+        set_non_blocking(read_fd);
+        let wake_fds = new_pipe();
+        set_non_blocking(wake_fds[0]);
+        RpcClient {
+            read_fd: read_fd,
+            write_fd: write_fd,
+            next_id: 1,
+            pending: HashMap::new(),
+            responses: HashMap::new(),
+            notifications: VecDeque::new(),
+            decoder: ::msgpack::Decoder::new(),
+            is_connected: true,
+            wake_fds: wake_fds
+        }
+    }
+
+    fn send(&mut self, method: &str, args: &::neovim::Array, pending: Pending) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        if !self.is_connected {
+            if let Pending::Callback(mut callback) = pending {
+                callback(Err(RpcError::Disconnected));
+            }
+            return id;
+        }
+        self.pending.insert(id, pending);
+        let msg = ::neovim::serialize_message(id, method, args);
+        write_all(self.write_fd, msg.as_ref());
+        id
+    }
+
+    fn dispatch(&mut self, msg_arr: ::neovim::Array) {
+        match msg_arr.get(0) {
+            Some(::neovim::Object::Integer(msg_type)) if msg_type == RESPONSE => {
+                let id = match msg_arr.get(1) {
+                    Some(::neovim::Object::Integer(id)) => id as u64,
+                    _ => return
+                };
+                let result = match msg_arr.get(2).and_then(get_error) {
+                    Some(e) => Err(e),
+                    None => Ok(msg_arr.get(3).unwrap_or(::neovim::Object::Nil))
+                };
+                match self.pending.remove(&id) {
+                    Some(Pending::Wait) => { self.responses.insert(id, result); },
+                    Some(Pending::Callback(mut callback)) => callback(result),
+                    Some(Pending::Ignore) => {
+                        if let Err(e) = result {
+                            println!("Error from nvim: {}", e);
+                        }
+                    },
+                    None => println!("Received response for unknown request {}", id)
+                }
+            },
+            Some(::neovim::Object::Integer(msg_type)) if msg_type == NOTIFICATION => {
+                self.notifications.push_back(msg_arr);
+            },
+            Some(::neovim::Object::Integer(msg_type)) if msg_type == REQUEST => {
+                println!("Ignoring request from nvim");
+            },
+            _ => println!("Received malformed message from nvim")
+        }
+    }
+
+    pub fn poll(&mut self) {
+        if self.is_connected && !read_available(self.read_fd, &mut self.decoder) {
+            self.close();
+        }
+        while let Some(msg) = self.decoder.next_message() {
+            self.dispatch(deserialize_message(msg));
+        }
+    }
+
+    // sends a request without waiting, only printing the error if there is one
+    pub fn request(&mut self, method: &str, args: &::neovim::Array) {
+        self.send(method, args, Pending::Ignore);
+    }
+
+    pub fn call_async(&mut self, method: &str, args: &::neovim::Array, callback: RpcCallback) {
+        self.send(method, args, Pending::Callback(callback));
+    }
+
+    // blocks until the response arrives, queuing any notifications received in the meantime.
+    // nvim's pipe won't become readable again for those, so the wake fd is used to get them handled.
+    pub fn call(&mut self, method: &str, args: &::neovim::Array, timeout_ms: i32) -> RpcResult {
+        let result = self.wait_for_response(method, args, timeout_ms);
+        if !self.notifications.is_empty() {
+            let byte : [c_uchar; 1] = [0];
+            unsafe { write(self.wake_fds[1], byte.as_ptr() as *const c_void, 1) };
+        }
+        result
+    }
+
+    fn wait_for_response(&mut self, method: &str, args: &::neovim::Array, timeout_ms: i32) -> RpcResult {
+        let id = self.send(method, args, Pending::Wait);
+        let deadline = now_ms() + timeout_ms as i64;
+
+        loop {
+            self.poll();
+            if let Some(result) = self.responses.remove(&id) {
+                return result;
+            }
+            if !self.is_connected {
+                self.pending.remove(&id);
+                return Err(RpcError::Disconnected);
+            }
+            let remaining = deadline - now_ms();
+            if remaining <= 0 {
+                // if the response shows up later, just throw it away
+                self.pending.insert(id, Pending::Ignore);
+                return Err(RpcError::Timeout);
+            }
+            wait_readable(self.read_fd, remaining as i32);
+        }
+    }
+
+    pub fn recv(&mut self) -> Option<::neovim::Array> {
+        self.poll();
+        self.notifications.pop_front()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.is_connected
+    }
+
+    // becomes readable when a blocking call left notifications in the queue
+    pub fn wake_fd(&self) -> c_int {
+        self.wake_fds[0]
+    }
+
+    pub fn close(&mut self) {
+        if self.is_connected {
+            self.is_connected = false;
+            close_fd(self.read_fd);
+            close_fd(self.write_fd);
+        }
     }
 }
@@ -0,0 +1,148 @@
+// true if the query's characters appear in the text in order, ignoring case
+pub fn is_subsequence(query: &str, text: &str) -> bool {
+    let mut text_chars = text.chars().flat_map(|c| c.to_lowercase());
+    for query_char in query.chars().flat_map(|c| c.to_lowercase()) {
+        if !text_chars.any(|text_char| text_char == query_char) {
+            return false;
+        }
+    }
+    true
+}
+
+// the query is split on slashes, so the last part has to match the name and
+// any parts before it have to match the directories above it, in order
+pub fn is_path_match(query: &str, components: &[&str]) -> bool {
+    let parts: Vec<&str> = query.split('/').filter(|part| part.trim().len() > 0).collect();
+    if parts.len() == 0 {
+        return true;
+    }
+    if components.len() == 0 {
+        return false;
+    }
+
+    let name = components[components.len() - 1];
+    let dirs = &components[..components.len() - 1];
+    if !is_subsequence(parts[parts.len() - 1].trim(), name) {
+        return false;
+    }
+
+    let mut i = 0;
+    for part in parts[..parts.len() - 1].iter() {
+        loop {
+            if i == dirs.len() {
+                return false;
+            }
+            i += 1;
+            if is_subsequence(part.trim(), dirs[i - 1]) {
+                break;
+            }
+        }
+    }
+    true
+}
+
+fn is_word_start(chars: &[char], i: usize) -> bool {
+    i == 0 || match chars[i - 1] {
+        '/' | '_' | '-' | '.' | ' ' => true,
+        _ => false
+    }
+}
+
+// scores how well the query matches the text, or returns None if it doesn't match at all.
+// consecutive characters and characters at the start of words count for more.
+pub fn score(query: &str, text: &str) -> Option<i64> {
+    let chars: Vec<char> = text.chars().map(|c| c.to_lowercase().next().unwrap_or(c)).collect();
+    let mut score = 0;
+    let mut i = 0;
+    let mut prev_match : Option<usize> = None;
+
+    for query_char in query.chars().map(|c| c.to_lowercase().next().unwrap_or(c)) {
+        if query_char == ' ' {
+            continue;
+        }
+        loop {
+            if i == chars.len() {
+                return None;
+            }
+            if chars[i] == query_char {
+                break;
+            }
+            i += 1;
+        }
+        score += 1;
+        if prev_match.map(|prev| prev + 1 == i).unwrap_or(false) {
+            score += 5;
+        }
+        if is_word_start(chars.as_ref(), i) {
+            score += 8;
+        }
+        prev_match = Some(i);
+        i += 1;
+    }
+
+    Some(score)
+}
+
+// matches in the file name count double, and shorter paths win ties
+pub fn score_path(query: &str, name: &str, path_str: &str) -> Option<i64> {
+    match score(query, path_str) {
+        Some(path_score) => {
+            let name_score = score(query, name).unwrap_or(0);
+            Some(path_score + name_score * 2 - (path_str.len() as i64) / 8)
+        },
+        None => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_path_match, score, score_path};
+
+    #[test]
+    fn scores_consecutive_and_word_start_matches() {
+        // 1 per character, 8 for starting a word, 5 for following the previous match
+        assert_eq!(score("ab", "ab"), Some(15));
+        assert_eq!(score("ab", "xab"), Some(7));
+        assert_eq!(score("ab", "a_b"), Some(18));
+        assert!(score("mr", "main.rs") > score("mr", "mister"));
+    }
+
+    #[test]
+    fn scores_ignore_case_and_spaces() {
+        assert_eq!(score("AB", "ab"), score("ab", "ab"));
+        assert_eq!(score("a b", "ab"), score("ab", "ab"));
+        assert_eq!(score("", "ab"), Some(0));
+    }
+
+    #[test]
+    fn no_score_without_a_match() {
+        assert_eq!(score("x", "abc"), None);
+        assert_eq!(score("ba", "ab"), None);
+        assert_eq!(score("a", ""), None);
+        assert_eq!(score_path("x", "main.rs", "src/main.rs"), None);
+    }
+
+    #[test]
+    fn name_matches_beat_directory_matches() {
+        let in_name = score_path("main", "main.rs", "src/main.rs").unwrap();
+        let in_dir = score_path("main", "lib.rs", "main/lib.rs").unwrap();
+        assert!(in_name > in_dir);
+    }
+
+    #[test]
+    fn shorter_paths_win_ties() {
+        let short = score_path("lib", "lib.rs", "a/lib.rs").unwrap();
+        let long = score_path("lib", "lib.rs", "a/b/c/d/e/lib.rs").unwrap();
+        assert!(short > long);
+    }
+
+    #[test]
+    fn path_matches_split_on_slashes() {
+        assert!(is_path_match("", &["src", "main.rs"]));
+        assert!(is_path_match("mn", &["src", "main.rs"]));
+        assert!(is_path_match("sr/mn", &["app", "src", "main.rs"]));
+        assert!(!is_path_match("mn/sr", &["app", "src", "main.rs"]));
+        assert!(!is_path_match("src", &["src", "main.rs"]));
+        assert!(!is_path_match("a", &[]));
+    }
+}
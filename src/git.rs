@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str;
+use std::thread;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Ignored,
+    Untracked,
+    Added,
+    Modified,
+    Conflicted
+}
+
+impl Status {
+    // directories show the most important status of anything inside them
+    fn priority(&self) -> i32 {
+        match *self {
+            Status::Ignored => 0,
+            Status::Untracked => 1,
+            Status::Added => 2,
+            Status::Modified => 3,
+            Status::Conflicted => 4
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match *self {
+            Status::Ignored => "I",
+            Status::Untracked => "?",
+            Status::Added => "A",
+            Status::Modified => "M",
+            Status::Conflicted => "C"
+        }
+    }
+
+    pub fn color(&self) -> &'static str {
+        match *self {
+            Status::Ignored => "gray",
+            Status::Untracked => "#4e9a06",
+            Status::Added => "#3465a4",
+            Status::Modified => "#c4a000",
+            Status::Conflicted => "#cc0000"
+        }
+    }
+}
+
+pub struct Statuses {
+    // the paths git listed
+    entries: HashMap<PathBuf, Status>,
+    // the directories above them
+    dirs: HashMap<PathBuf, Status>
+}
+
+pub struct StatusUpdate {
+    pub project_path: PathBuf,
+    pub statuses: Statuses
+}
+
+// the first column is the index and the second is the work tree. new paths, including intent-to-add
+// files and staged renames and copies, show as added even if they've been changed since.
+fn parse_code(code: &str) -> Status {
+    match code {
+        "??" => Status::Untracked,
+        "!!" => Status::Ignored,
+        "DD" | "AU" | "UD" | "UA" | "DU" | "AA" | "UU" => Status::Conflicted,
+        _ => {
+            let mut columns = code.chars();
+            let index = columns.next().unwrap_or(' ');
+            let work_tree = columns.next().unwrap_or(' ');
+            match (index, work_tree) {
+                ('A', _) | ('R', _) | ('C', _) | (_, 'A') => Status::Added,
+                _ => Status::Modified
+            }
+        }
+    }
+}
+
+fn insert_status(statuses: &mut HashMap<PathBuf, Status>, path: PathBuf, status: Status) {
+    let is_more_important = match statuses.get(&path) {
+        Some(old_status) => status.priority() > old_status.priority(),
+        None => true
+    };
+    if is_more_important {
+        statuses.insert(path, status);
+    }
+}
+
+// parses the output of `git status --porcelain -z`, keeping the paths inside the project.
+// paths that aren't valid UTF-8 are skipped, since the tree can't show them anyway.
+fn parse_status(top_path: &Path, project_path: &Path, output: &[u8]) -> Statuses {
+    let mut statuses = Statuses { entries: HashMap::new(), dirs: HashMap::new() };
+    let mut entries = output.split(|b| *b == 0);
+
+    while let Some(entry) = entries.next() {
+        if entry.len() < 4 {
+            continue;
+        }
+        // renames and copies are followed by the original path, which is gone now
+        if entry[0] == b'R' || entry[0] == b'C' {
+            entries.next();
+        }
+        let entry = match str::from_utf8(entry) {
+            Ok(entry) => entry,
+            Err(_) => continue
+        };
+        let code = &entry[..2];
+
+        let path = top_path.join(entry[3..].trim_right_matches('/'));
+        if !path.starts_with(project_path) {
+            continue;
+        }
+        let status = parse_code(code);
+
+        // ignored paths don't affect the directories above them
+        if status != Status::Ignored {
+            let mut parent = path.parent();
+            while let Some(dir) = parent {
+                if !dir.starts_with(project_path) {
+                    break;
+                }
+                insert_status(&mut statuses.dirs, dir.to_path_buf(), status);
+                parent = dir.parent();
+            }
+        }
+        insert_status(&mut statuses.entries, path, status);
+    }
+
+    statuses
+}
+
+fn run_git(project_path: &Path, args: &[&str]) -> Option<Vec<u8>> {
+    let output = Command::new("git").args(args).current_dir(project_path)
+        .stdin(Stdio::null()).stderr(Stdio::null()).output();
+    match output {
+        Ok(output) => {
+            if output.status.success() {
+                Some(output.stdout)
+            } else {
+                // not a git repo
+                None
+            }
+        },
+        Err(e) => {
+            println!("Error running git: {}", e);
+            None
+        }
+    }
+}
+
+fn read_status(project_path: &Path) -> Statuses {
+    let top_str = run_git(project_path, &["rev-parse", "--show-toplevel"]).and_then(|output| String::from_utf8(output).ok());
+    if let Some(top_str) = top_str {
+        let top_path = PathBuf::from(top_str.trim_right());
+        if let Some(output) = run_git(project_path, &["status", "--porcelain", "-z", "--ignored"]) {
+            return parse_status(&top_path, project_path, output.as_ref());
+        }
+    }
+    Statuses { entries: HashMap::new(), dirs: HashMap::new() }
+}
+
+// reads the status in the background, and if it's already being read it's read again afterwards
+pub fn refresh_status(state: &mut ::utils::State, project_path: &Path) {
+    let project_path = project_path.to_path_buf();
+    if state.git_pending.contains(&project_path) {
+        state.git_stale.insert(project_path);
+        return;
+    }
+    state.git_pending.insert(project_path.clone());
+
+    let notifier = state.git_notifier.clone();
+    thread::spawn(move || {
+        let statuses = read_status(&project_path);
+        notifier.send(StatusUpdate { project_path: project_path, statuses: statuses });
+    });
+}
+
+pub fn refresh_all(state: &mut ::utils::State) {
+    for path_str in state.projects.clone().iter() {
+        refresh_status(state, Path::new(path_str));
+    }
+}
+
+// refreshes the projects that contain any of the paths
+pub fn refresh_paths(state: &mut ::utils::State, paths: &[PathBuf]) {
+    let mut project_paths = Vec::new();
+    for path_str in state.projects.iter() {
+        if paths.iter().any(|path| path.starts_with(path_str)) {
+            project_paths.push(PathBuf::from(path_str));
+        }
+    }
+    for project_path in project_paths.iter() {
+        refresh_status(state, project_path);
+    }
+}
+
+pub fn handle_update(state: &mut ::utils::State, update: StatusUpdate) {
+    state.git_pending.remove(&update.project_path);
+
+    if state.git_stale.remove(&update.project_path) {
+        refresh_status(state, &update.project_path);
+    }
+
+    if let Some(path_str) = update.project_path.to_str() {
+        if !state.projects.contains(&path_str.to_string()) {
+            state.git_statuses.remove(&update.project_path);
+            return;
+        }
+    }
+    state.git_statuses.insert(update.project_path, update.statuses);
+    ::ui::update_git_status(state);
+}
+
+// untracked and ignored directories are listed without their contents
+pub fn get_status(state: &::utils::State, path: &Path) -> Option<Status> {
+    for (project_path, statuses) in state.git_statuses.iter() {
+        if path.starts_with(project_path) {
+            if let Some(status) = get_status_in(statuses, project_path, path) {
+                return Some(status);
+            }
+        }
+    }
+    None
+}
+
+fn get_status_in(statuses: &Statuses, project_path: &Path, path: &Path) -> Option<Status> {
+    let path_buf = path.to_path_buf();
+    match (statuses.entries.get(&path_buf), statuses.dirs.get(&path_buf)) {
+        (Some(a), Some(b)) => return Some(if a.priority() > b.priority() { *a } else { *b }),
+        (Some(a), None) => return Some(*a),
+        (None, Some(b)) => return Some(*b),
+        (None, None) => {}
+    }
+    let mut parent = path.parent();
+    while let Some(dir) = parent {
+        if !dir.starts_with(project_path) {
+            break;
+        }
+        match statuses.entries.get(&dir.to_path_buf()) {
+            Some(&Status::Ignored) => return Some(Status::Ignored),
+            Some(&Status::Untracked) => return Some(Status::Untracked),
+            _ => {}
+        }
+        parent = dir.parent();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_status_in, parse_status, Status};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn parses_statuses_and_dirs() {
+        let output = b" M src/main.rs\0?? notes/\0A  src/new.rs\0UU Cargo.lock\0";
+        let statuses = parse_status(Path::new("/repo"), Path::new("/repo"), output);
+        assert!(statuses.entries.get(&PathBuf::from("/repo/src/main.rs")) == Some(&Status::Modified));
+        assert!(statuses.entries.get(&PathBuf::from("/repo/notes")) == Some(&Status::Untracked));
+        assert!(statuses.entries.get(&PathBuf::from("/repo/src/new.rs")) == Some(&Status::Added));
+        assert!(statuses.entries.get(&PathBuf::from("/repo/Cargo.lock")) == Some(&Status::Conflicted));
+        // the most important status inside a dir wins
+        assert!(statuses.dirs.get(&PathBuf::from("/repo/src")) == Some(&Status::Modified));
+        assert!(statuses.dirs.get(&PathBuf::from("/repo")) == Some(&Status::Conflicted));
+    }
+
+    #[test]
+    fn new_paths_show_as_added() {
+        let output = b" A intent.rs\0AM staged.rs\0C  copy.rs\0orig.rs\0RM moved.rs\0old.rs\0 D gone.rs\0";
+        let statuses = parse_status(Path::new("/repo"), Path::new("/repo"), output);
+        assert!(statuses.entries.get(&PathBuf::from("/repo/intent.rs")) == Some(&Status::Added));
+        assert!(statuses.entries.get(&PathBuf::from("/repo/staged.rs")) == Some(&Status::Added));
+        assert!(statuses.entries.get(&PathBuf::from("/repo/copy.rs")) == Some(&Status::Added));
+        assert!(statuses.entries.get(&PathBuf::from("/repo/moved.rs")) == Some(&Status::Added));
+        assert!(statuses.entries.get(&PathBuf::from("/repo/gone.rs")) == Some(&Status::Modified));
+        assert_eq!(statuses.entries.len(), 5);
+    }
+
+    #[test]
+    fn skips_the_original_path_of_renames() {
+        let output = b"R  src/new name.rs\0src/old.rs\0 M README.md\0";
+        let statuses = parse_status(Path::new("/repo"), Path::new("/repo"), output);
+        assert!(statuses.entries.get(&PathBuf::from("/repo/src/new name.rs")) == Some(&Status::Added));
+        assert!(statuses.entries.get(&PathBuf::from("/repo/src/old.rs")).is_none());
+        assert!(statuses.entries.get(&PathBuf::from("/repo/README.md")) == Some(&Status::Modified));
+        assert_eq!(statuses.entries.len(), 2);
+    }
+
+    #[test]
+    fn ignored_entries_dont_mark_their_dirs() {
+        let output = b"!! target/\0!! src/gen/out.rs\0";
+        let statuses = parse_status(Path::new("/repo"), Path::new("/repo"), output);
+        assert!(statuses.entries.get(&PathBuf::from("/repo/target")) == Some(&Status::Ignored));
+        assert!(statuses.entries.get(&PathBuf::from("/repo/src/gen/out.rs")) == Some(&Status::Ignored));
+        assert!(statuses.dirs.is_empty());
+        assert!(get_status_in(&statuses, Path::new("/repo"), Path::new("/repo/target/debug/app")) == Some(Status::Ignored));
+    }
+
+    #[test]
+    fn keeps_paths_inside_the_project() {
+        let output = b" M app/src/main.rs\0 M lib/src/lib.rs\0";
+        let statuses = parse_status(Path::new("/repo"), Path::new("/repo/app"), output);
+        assert!(statuses.entries.get(&PathBuf::from("/repo/app/src/main.rs")).is_some());
+        assert!(statuses.entries.get(&PathBuf::from("/repo/lib/src/lib.rs")).is_none());
+        assert!(statuses.dirs.get(&PathBuf::from("/repo")).is_none());
+    }
+
+    #[test]
+    fn skips_only_invalid_entries() {
+        let output = b" M bad\xff.rs\0R  new\xfe.rs\0old.rs\0 M good.rs\0";
+        let statuses = parse_status(Path::new("/repo"), Path::new("/repo"), output);
+        assert!(statuses.entries.get(&PathBuf::from("/repo/good.rs")) == Some(&Status::Modified));
+        assert!(statuses.entries.get(&PathBuf::from("/repo/old.rs")).is_none());
+        assert_eq!(statuses.entries.len(), 1);
+    }
+}
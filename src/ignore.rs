@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// files later in the list take precedence, like in git and ripgrep
+static IGNORE_FILES : &'static [&'static str] = &[".gitignore", ".ignore"];
+
+pub fn is_ignore_file(path: &Path) -> bool {
+    match path.file_name().and_then(|s| s.to_str()) {
+        Some(leaf_str) => IGNORE_FILES.contains(&leaf_str),
+        None => false
+    }
+}
+
+struct Rule {
+    pattern: Vec<char>,
+    is_negated: bool,
+    is_dir_only: bool,
+    // anchored patterns are matched against the path relative to the base dir,
+    // and the rest are matched against the name alone
+    is_anchored: bool,
+    base: PathBuf
+}
+
+// follows gitignore's rules, so patterns from the settings behave like lines in a .gitignore at the project root
+pub struct Matcher {
+    root: PathBuf,
+    rules: Vec<Rule>,
+    dir_rules: HashMap<PathBuf, Vec<Rule>>
+}
+
+fn parse_rule(line: &str, base: &Path) -> Option<Rule> {
+    let mut pattern = line.trim_right_matches(|c: char| c == '\r' || c == '\n');
+    if pattern.ends_with(" ") && !pattern.ends_with("\\ ") {
+        pattern = pattern.trim_right();
+    }
+    if pattern.len() == 0 || pattern.starts_with("#") {
+        return None;
+    }
+
+    let is_negated = pattern.starts_with("!");
+    if is_negated {
+        pattern = &pattern[1..];
+    } else if pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+        pattern = &pattern[1..];
+    }
+
+    let is_dir_only = pattern.ends_with("/");
+    let pattern = pattern.trim_right_matches('/');
+    if pattern.len() == 0 {
+        return None;
+    }
+
+    let is_anchored = pattern.contains("/");
+    let pattern = pattern.trim_left_matches('/');
+
+    Some(Rule {
+        pattern: pattern.chars().collect(),
+        is_negated: is_negated,
+        is_dir_only: is_dir_only,
+        is_anchored: is_anchored,
+        base: base.to_path_buf()
+    })
+}
+
+fn read_rules(dir: &Path) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for file_name in IGNORE_FILES.iter() {
+        if let Ok(mut f) = fs::File::open(&dir.join(file_name)) {
+            let mut contents = String::new();
+            if f.read_to_string(&mut contents).is_ok() {
+                for line in contents.lines() {
+                    if let Some(rule) = parse_rule(line, dir) {
+                        rules.push(rule);
+                    }
+                }
+            }
+        }
+    }
+    rules
+}
+
+// matches a single character against the part of the pattern at p that isn't a star,
+// returning where the rest of the pattern starts
+fn match_char(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    if p >= pattern.len() {
+        return None;
+    }
+    match pattern[p] {
+        '?' => if c != '/' { Some(p + 1) } else { None },
+        '[' => {
+            match pattern[p + 1..].iter().position(|c| *c == ']') {
+                Some(end) => {
+                    if c == '/' {
+                        return None;
+                    }
+                    let class = &pattern[p + 1..p + 1 + end];
+                    let (is_negated, class) = if class.len() > 0 && (class[0] == '!' || class[0] == '^') {
+                        (true, &class[1..])
+                    } else {
+                        (false, class)
+                    };
+                    let mut is_match = false;
+                    let mut i = 0;
+                    while i < class.len() {
+                        if i + 2 < class.len() && class[i + 1] == '-' {
+                            if class[i] <= c && c <= class[i + 2] {
+                                is_match = true;
+                            }
+                            i += 3;
+                        } else {
+                            if class[i] == c {
+                                is_match = true;
+                            }
+                            i += 1;
+                        }
+                    }
+                    if is_match != is_negated { Some(p + end + 2) } else { None }
+                },
+                None => if c == '[' { Some(p + 1) } else { None }
+            }
+        },
+        '\\' if p + 1 < pattern.len() => if pattern[p + 1] == c { Some(p + 2) } else { None },
+        pattern_char => if pattern_char == c { Some(p + 1) } else { None }
+    }
+}
+
+// the kinds of star in a glob, which are backtracked to separately
+static STAR : usize = 0;
+// a trailing "/**", which matches everything inside
+static ANY_STAR : usize = 1;
+// "**/" at the start or after a slash, which matches any number of dirs
+static DIR_STAR : usize = 2;
+
+// matches a glob where * and ? stop at slashes and ** crosses them. like in git, ** is only
+// special as a whole path component, and anywhere else it's the same as *.
+// instead of trying every split at every star, only the last star of each kind is
+// backtracked to, since anything an earlier one could take a later one can take too.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    let mut p = 0;
+    let mut t = 0;
+    // where the pattern resumes after the last star of each kind, and where its text ends so far
+    let mut marks: [Option<(usize, usize)>; 3] = [None; 3];
+
+    loop {
+        if p < pattern.len() && pattern[p] == '*' {
+            let is_double = p + 1 < pattern.len() && pattern[p + 1] == '*';
+            let is_component = is_double && (p == 0 || pattern[p - 1] == '/') &&
+                (p + 2 == pattern.len() || pattern[p + 2] == '/');
+            if is_component && p + 2 < pattern.len() {
+                p += 3;
+                marks[DIR_STAR] = Some((p, t));
+            } else if is_component {
+                p += 2;
+                marks[ANY_STAR] = Some((p, t));
+            } else {
+                p += if is_double { 2 } else { 1 };
+                marks[STAR] = Some((p, t));
+            }
+            continue;
+        }
+        if p == pattern.len() && t == text.len() {
+            return true;
+        }
+        if t < text.len() {
+            if let Some(next_p) = match_char(pattern, p, text[t]) {
+                p = next_p;
+                t += 1;
+                continue;
+            }
+        }
+
+        // let the most recent star that can take more text have it, starting over from there
+        let mut kinds: Vec<usize> = (0..marks.len()).filter(|kind| marks[*kind].is_some()).collect();
+        kinds.sort_by(|a, b| marks[*b].cmp(&marks[*a]));
+        let mut is_retrying = false;
+        for kind in kinds.into_iter() {
+            let (star_p, star_t) = marks[kind].unwrap();
+            let next_t = if kind == DIR_STAR {
+                text[star_t..].iter().position(|c| *c == '/').map(|i| star_t + i + 1)
+            } else if star_t < text.len() && (kind == ANY_STAR || text[star_t] != '/') {
+                Some(star_t + 1)
+            } else {
+                None
+            };
+            if let Some(next_t) = next_t {
+                // the stars after it will be found again on the way through the pattern
+                for mark in marks.iter_mut() {
+                    if mark.map(|(mark_p, _)| mark_p > star_p).unwrap_or(false) {
+                        *mark = None;
+                    }
+                }
+                marks[kind] = Some((star_p, next_t));
+                p = star_p;
+                t = next_t;
+                is_retrying = true;
+                break;
+            }
+        }
+        if !is_retrying {
+            return false;
+        }
+    }
+}
+
+impl Rule {
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.is_dir_only && !is_dir {
+            return false;
+        }
+        let text_str = if self.is_anchored {
+            path.relative_from(&self.base).and_then(|p| p.to_str())
+        } else {
+            path.file_name().and_then(|s| s.to_str())
+        };
+        match text_str {
+            Some(s) => {
+                let text: Vec<char> = s.chars().collect();
+                glob_match(self.pattern.as_ref(), text.as_ref())
+            },
+            None => false
+        }
+    }
+}
+
+// true if any of the patterns match the file or a directory above it,
+// reading them the same way as lines in an ignore file at the root
+pub fn is_any_match(patterns: &[String], root: &Path, path: &Path) -> bool {
+    let rules: Vec<Rule> = patterns.iter().filter_map(|pattern| parse_rule(pattern.as_ref(), root)).collect();
+    let mut current = Some(path);
+    let mut is_dir = false;
+    while let Some(p) = current {
+        if p == root || !p.starts_with(root) {
+            break;
+        }
+        if rules.iter().any(|rule| !rule.is_negated && rule.matches(p, is_dir)) {
+            return true;
+        }
+        current = p.parent();
+        is_dir = true;
+    }
+    false
+}
+
+impl Matcher {
+    pub fn new(root: &Path, patterns: &[String]) -> Matcher {
+        let mut rules = Vec::new();
+        for pattern in patterns.iter() {
+            if let Some(rule) = parse_rule(pattern.as_ref(), root) {
+                rules.push(rule);
+            }
+        }
+        rules.extend(read_rules(root).into_iter());
+
+        Matcher {
+            root: root.to_path_buf(),
+            rules: rules,
+            dir_rules: HashMap::new()
+        }
+    }
+
+    // only looks at the path itself, so callers walking the tree should stop at ignored dirs
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        if path == self.root.as_path() || !path.starts_with(&self.root) {
+            return false;
+        }
+
+        // collect the nested dirs between the root and the path, whose ignore files apply too
+        let mut dirs = Vec::new();
+        let mut parent = path.parent();
+        while let Some(dir) = parent {
+            if dir == self.root.as_path() {
+                break;
+            }
+            dirs.push(dir.to_path_buf());
+            parent = dir.parent();
+        }
+        dirs.reverse();
+
+        let mut is_ignored = false;
+        for rule in self.rules.iter() {
+            if rule.matches(path, is_dir) {
+                is_ignored = !rule.is_negated;
+            }
+        }
+        for dir in dirs.into_iter() {
+            if !self.dir_rules.contains_key(&dir) {
+                let rules = read_rules(&dir);
+                self.dir_rules.insert(dir.clone(), rules);
+            }
+            if let Some(rules) = self.dir_rules.get(&dir) {
+                for rule in rules.iter() {
+                    if rule.matches(path, is_dir) {
+                        is_ignored = !rule.is_negated;
+                    }
+                }
+            }
+        }
+        is_ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_match, parse_rule, Matcher};
+    use std::path::Path;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let text: Vec<char> = text.chars().collect();
+        glob_match(pattern.as_ref(), text.as_ref())
+    }
+
+    #[test]
+    fn star_stops_at_slashes() {
+        assert!(is_match("*.rs", "main.rs"));
+        assert!(is_match("src/*.rs", "src/main.rs"));
+        assert!(!is_match("src/*.rs", "src/bin/main.rs"));
+        assert!(is_match("?.rs", "a.rs"));
+        assert!(!is_match("?", "/"));
+        assert!(is_match("[a-c]x", "bx"));
+        assert!(!is_match("[!a-c]x", "bx"));
+        assert!(is_match("\\*", "*"));
+        assert!(!is_match("\\*", "a"));
+    }
+
+    #[test]
+    fn double_star_crosses_slashes() {
+        assert!(is_match("**/foo", "foo"));
+        assert!(is_match("**/foo", "a/b/foo"));
+        assert!(!is_match("**/foo", "afoo"));
+        assert!(is_match("a/**/b", "a/b"));
+        assert!(is_match("a/**/b", "a/x/y/b"));
+        assert!(!is_match("a/**/b", "a/xb"));
+        assert!(is_match("a/**", "a/x/y"));
+        assert!(!is_match("a/**", "b/x"));
+        assert!(is_match("**/*.rs", "src/bin/main.rs"));
+        assert!(is_match("src/**/t*/*.rs", "src/a/test/b/tests/x.rs"));
+        assert!(!is_match("src/**/t*/*.rs", "src/a/test/b/x.rs/y"));
+        // inside a name it's just a star
+        assert!(is_match("a**b", "axyb"));
+        assert!(!is_match("a**b", "a/b"));
+    }
+
+    #[test]
+    fn many_stars_dont_backtrack_forever() {
+        let text: String = (0..20).map(|_| "aaaaaaaaaa/").collect::<Vec<&str>>().concat() + "b";
+        assert!(!is_match("**/*a*a*a*a*a*a*a*a*c", text.as_ref()));
+        assert!(!is_match("*a*a*a*a*a*a*a*a*a*a*a*c", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"));
+        assert!(is_match("*a*a*a*a*a*a*a*a*a*a*a*b", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaab"));
+    }
+
+    #[test]
+    fn parses_rules() {
+        let base = Path::new("/project");
+        assert!(parse_rule("", base).is_none());
+        assert!(parse_rule("# comment", base).is_none());
+        assert!(parse_rule("/", base).is_none());
+
+        let rule = parse_rule("target/", base).unwrap();
+        assert!(rule.is_dir_only && !rule.is_anchored && !rule.is_negated);
+        assert_eq!(rule.pattern, "target".chars().collect::<Vec<char>>());
+
+        let rule = parse_rule("/build", base).unwrap();
+        assert!(rule.is_anchored && !rule.is_dir_only);
+        assert_eq!(rule.pattern, "build".chars().collect::<Vec<char>>());
+
+        let rule = parse_rule("doc/frotz", base).unwrap();
+        assert!(rule.is_anchored);
+
+        let rule = parse_rule("!keep.log  ", base).unwrap();
+        assert!(rule.is_negated);
+        assert_eq!(rule.pattern, "keep.log".chars().collect::<Vec<char>>());
+
+        let rule = parse_rule("\\#notes", base).unwrap();
+        assert!(!rule.is_negated);
+        assert_eq!(rule.pattern, "#notes".chars().collect::<Vec<char>>());
+    }
+
+    #[test]
+    fn matches_rules_against_paths() {
+        let base = Path::new("/project");
+        let rule = parse_rule("target/", base).unwrap();
+        assert!(rule.matches(Path::new("/project/a/target"), true));
+        assert!(!rule.matches(Path::new("/project/a/target"), false));
+
+        let rule = parse_rule("/build", base).unwrap();
+        assert!(rule.matches(Path::new("/project/build"), true));
+        assert!(!rule.matches(Path::new("/project/src/build"), true));
+
+        let rule = parse_rule("**/gen/*.rs", base).unwrap();
+        assert!(rule.matches(Path::new("/project/src/gen/a.rs"), false));
+        assert!(rule.matches(Path::new("/project/gen/a.rs"), false));
+    }
+
+    #[test]
+    fn later_negations_win() {
+        // the root doesn't exist, so there are no ignore files to read
+        let root = Path::new("/nonexistent-project");
+        let patterns = vec!["*.log".to_string(), "!keep.log".to_string()];
+        let mut matcher = Matcher::new(root, patterns.as_ref());
+        assert!(matcher.is_ignored(&root.join("debug.log"), false));
+        assert!(!matcher.is_ignored(&root.join("keep.log"), false));
+        assert!(!matcher.is_ignored(&root.join("main.rs"), false));
+        assert!(!matcher.is_ignored(root, true));
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, PathExt};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+pub struct IndexUpdate {
+    pub project_path: PathBuf,
+    pub generation: u64,
+    pub files: BTreeSet<PathBuf>
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name().and_then(|s| s.to_str()).map(|s| s.starts_with(".")).unwrap_or(true)
+}
+
+// true if the path or any dir between it and the project is hidden or ignored
+fn is_excluded(matcher: &mut ::ignore::Matcher, project_path: &Path, path: &Path) -> bool {
+    let mut current = Some(path);
+    let mut is_dir = false;
+    while let Some(p) = current {
+        if p == project_path || !p.starts_with(project_path) {
+            break;
+        }
+        if is_hidden(p) || matcher.is_ignored(p, is_dir) {
+            return true;
+        }
+        current = p.parent();
+        is_dir = true;
+    }
+    false
+}
+
+pub fn add_files(matcher: &mut ::ignore::Matcher, dir: &Path, files: &mut BTreeSet<PathBuf>) {
+    if let Ok(children) = fs::read_dir(dir) {
+        for child in children {
+            if let Ok(dir_entry) = child {
+                let path = dir_entry.path();
+                let is_dir = path.is_dir();
+                if is_hidden(&path) || matcher.is_ignored(&path, is_dir) {
+                    continue;
+                }
+                if is_dir {
+                    add_files(matcher, &path, files);
+                } else {
+                    files.insert(path);
+                }
+            }
+        }
+    }
+}
+
+// lists the project's files in the background, leaving out hidden and ignored ones.
+// only the most recent scan of each project is kept, since earlier ones may have missed changes.
+pub fn index_project(state: &mut ::utils::State, project_path: &Path) {
+    let project_path = project_path.to_path_buf();
+    let mut matcher = ::utils::get_matcher(state, &project_path);
+    let notifier = state.index_notifier.clone();
+    state.index_generation += 1;
+    let generation = state.index_generation;
+    state.index_pending.insert(project_path.clone(), generation);
+
+    thread::spawn(move || {
+        let mut files = BTreeSet::new();
+        add_files(&mut matcher, &project_path, &mut files);
+        notifier.send(IndexUpdate { project_path: project_path, generation: generation, files: files });
+    });
+}
+
+pub fn index_all(state: &mut ::utils::State) {
+    for path_str in state.projects.clone().iter() {
+        index_project(state, Path::new(path_str));
+    }
+}
+
+pub fn handle_update(state: &mut ::utils::State, update: IndexUpdate) {
+    if state.index_pending.get(&update.project_path) != Some(&update.generation) {
+        return;
+    }
+    state.index_pending.remove(&update.project_path);
+
+    // the project may have been removed while it was being indexed
+    if let Some(path_str) = update.project_path.to_str() {
+        if state.projects.contains(&path_str.to_string()) {
+            state.file_index.insert(update.project_path, update.files);
+        }
+    }
+}
+
+// a scan that's still running may have already passed the path, so it's scanned again
+fn needs_index(state: &::utils::State, project_path: &Path, path: &Path) -> bool {
+    path.is_dir() || ::ignore::is_ignore_file(path) || ::utils::is_settings_file(path) ||
+        state.index_pending.contains_key(project_path)
+}
+
+fn add_path(
+    state: &mut ::utils::State,
+    matchers: &mut HashMap<PathBuf, ::ignore::Matcher>,
+    path: &Path,
+    projects_to_index: &mut Vec<PathBuf>)
+{
+    if let Some(project_path) = ::utils::get_root_path(state, path) {
+        // new directories may already have files in them, like after a move,
+        // and new ignore files can change what belongs in the index
+        if needs_index(state, &project_path, path) {
+            if !projects_to_index.contains(&project_path) {
+                projects_to_index.push(project_path);
+            }
+        } else {
+            // hidden and ignored directories are watched while they're shown, so check the dirs above it too
+            if !matchers.contains_key(&project_path) {
+                let matcher = ::utils::get_matcher(state, &project_path);
+                matchers.insert(project_path.clone(), matcher);
+            }
+            let is_skipped = match matchers.get_mut(&project_path) {
+                Some(matcher) => is_excluded(matcher, &project_path, path),
+                None => true
+            };
+            if !is_skipped {
+                if let Some(files) = state.file_index.get_mut(&project_path) {
+                    files.insert(path.to_path_buf());
+                }
+            }
+        }
+    }
+}
+
+fn remove_path(state: &mut ::utils::State, path: &Path, projects_to_index: &mut Vec<PathBuf>) {
+    if let Some(project_path) = ::utils::get_root_path(state, path) {
+        if needs_index(state, &project_path, path) && !projects_to_index.contains(&project_path) {
+            projects_to_index.push(project_path.clone());
+        }
+        if let Some(files) = state.file_index.get_mut(&project_path) {
+            let removed: Vec<PathBuf> = files.iter().filter(|file| file.starts_with(path)).cloned().collect();
+            for file in removed.iter() {
+                files.remove(file);
+            }
+        }
+    }
+}
+
+// keeps the index up to date with changes from the file watcher
+pub fn apply_changes(state: &mut ::utils::State, changes: &[::watcher::Change]) {
+    let mut projects_to_index = Vec::new();
+    // changed ignore and settings files reindex the project anyway, so each matcher is built once per batch
+    let mut matchers = HashMap::new();
+
+    for change in changes.iter() {
+        match *change {
+            ::watcher::Change::Created(ref path) => add_path(state, &mut matchers, path, &mut projects_to_index),
+            ::watcher::Change::Removed(ref path) => remove_path(state, path, &mut projects_to_index),
+            ::watcher::Change::Moved(ref from, ref to) => {
+                remove_path(state, from, &mut projects_to_index);
+                add_path(state, &mut matchers, to, &mut projects_to_index);
+            },
+            ::watcher::Change::Modified(ref path) => {
+                if let Some(project_path) = ::utils::get_root_path(state, path) {
+                    if !projects_to_index.contains(&project_path) {
+                        projects_to_index.push(project_path);
+                    }
+                }
+            },
+            ::watcher::Change::Rescan => {
+                for path_str in state.projects.iter() {
+                    let project_path = PathBuf::from(path_str);
+                    if !projects_to_index.contains(&project_path) {
+                        projects_to_index.push(project_path);
+                    }
+                }
+            }
+        }
+    }
+
+    for project_path in projects_to_index.iter() {
+        index_project(state, project_path);
+    }
+}
@@ -10,19 +10,86 @@ extern crate rustc_serialize;
 use glib::traits::Connect;
 use gtk::traits::*;
 use gtk::{signals, widgets};
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs::{self, PathExt};
 use std::io::Write;
 use std::ops::Deref;
+use std::path::PathBuf;
 use std::ffi::AsOsStr;
-use std::thread;
 
 mod builders;
+mod diagnostics;
 mod ffi;
+mod fuzzy;
+mod git;
+mod ignore;
+mod index;
+mod msgpack;
+mod nvim;
+mod palette;
 mod projects;
+mod search;
+mod session;
+mod tabs;
+mod trash;
 mod ui;
 mod utils;
+mod watcher;
+
+// handles everything nvim has sent, whether it was just read from the pipe or queued during a blocking call
+fn handle_nvim_messages(
+    state: &mut ::utils::State,
+    client: &mut ::nvim::Client,
+    project_tree: &mut widgets::TreeView,
+    build_buttons: &mut widgets::Box,
+    build_terms: &mut widgets::Stack,
+    buffer_notebook: &mut widgets::Notebook)
+{
+    let mut received = false;
+    let mut buffers_changed = false;
+
+    while let Some(recv_arr) = client.recv() {
+        received = true;
+        if let Some(neovim::Object::String(event_name)) = recv_arr.get(1) {
+            match event_name.as_ref() {
+                "bufenter" => {
+                    buffers_changed = true;
+                    if let Some(neovim::Object::Array(event_args)) = recv_arr.get(2) {
+                        if let Some(neovim::Object::String(path_str)) = event_args.get(0) {
+                            ::projects::apply_project_settings(state, client, path_str.as_ref());
+                            ::palette::add_recent_file(state, path_str.as_ref());
+                            ::builders::update_signs(state, client, path_str.as_ref());
+                            state.selection = Some(path_str);
+                            ::utils::write_prefs(state);
+                        }
+                    }
+                },
+                "bufwrite" => {
+                    buffers_changed = true;
+                    if let Some(neovim::Object::Array(event_args)) = recv_arr.get(2) {
+                        if let Some(neovim::Object::String(path_str)) = event_args.get(0) {
+                            ::git::refresh_paths(state, &[PathBuf::from(path_str)]);
+                        }
+                    }
+                },
+                "bufadd" | "bufdelete" | "bufmodified" => buffers_changed = true,
+                "vimleave" => gtk::main_quit(),
+                _ => (),
+            }
+        }
+    }
+
+    if received {
+        ::ui::update_selection(state, project_tree);
+        ::builders::show_builder(state, client, build_buttons, build_terms);
+        ::builders::set_builders_font_size(state);
+    }
+    if buffers_changed {
+        ::tabs::update_tabs(state, client, buffer_notebook);
+    }
+}
 
 fn gui_main(pty: &mut widgets::VtePty, read_fd: i32, write_fd: i32, pid: i32) {
     gtk::init();
@@ -33,19 +100,30 @@ fn gui_main(pty: &mut widgets::VtePty, read_fd: i32, write_fd: i32, pid: i32) {
     let import_button = widgets::Button::new_with_label("Import").unwrap();
     let rename_button = widgets::Button::new_with_label("Rename").unwrap();
     let remove_button = widgets::Button::new_with_label("Remove").unwrap();
+    let go_to_file_button = widgets::Button::new_with_label("Go to File").unwrap();
+    let find_button = widgets::Button::new_with_label("Find").unwrap();
+    let show_ignored_button = widgets::ToggleButton::new_with_label("Show All").unwrap();
+    show_ignored_button.set_tooltip_text("Show hidden and ignored files");
 
     let project_buttons = widgets::Box::new(gtk::Orientation::Horizontal, 0).unwrap();
     project_buttons.add(&new_button);
     project_buttons.add(&import_button);
     project_buttons.add(&rename_button);
     project_buttons.add(&remove_button);
+    project_buttons.add(&go_to_file_button);
+    project_buttons.add(&find_button);
+    project_buttons.add(&show_ignored_button);
 
     let mut project_tree = widgets::TreeView::new().unwrap();
     let selection = project_tree.get_selection().unwrap();
-    let column_types = [glib::Type::String, glib::Type::String];
+    let column_types = [glib::Type::String, glib::Type::String, glib::Type::String,
+                        glib::Type::String, glib::Type::String, glib::Type::String, glib::Type::Bool];
     let store = widgets::TreeStore::new(&column_types).unwrap();
     let model = store.get_model().unwrap();
-    project_tree.set_model(&model);
+    let tree_filter = widgets::TreeModelFilter::new(&model, None).unwrap();
+    tree_filter.set_visible_column(6);
+    let filter_model = tree_filter.get_model().unwrap();
+    project_tree.set_model(&filter_model);
     project_tree.set_headers_visible(false);
     project_tree.set_can_focus(false);
 
@@ -54,12 +132,44 @@ fn gui_main(pty: &mut widgets::VtePty, read_fd: i32, write_fd: i32, pid: i32) {
 
     let column = widgets::TreeViewColumn::new().unwrap();
     let cell = widgets::CellRendererText::new().unwrap();
+    let git_cell = widgets::CellRendererText::new().unwrap();
+    let status_cell = widgets::CellRendererPixbuf::new().unwrap();
     column.pack_start(&cell, true);
+    column.pack_start(&git_cell, false);
+    column.pack_start(&status_cell, false);
     column.add_attribute(&cell, "text", 0);
+    column.add_attribute(&cell, "foreground", 3);
+    column.add_attribute(&git_cell, "text", 4);
+    column.add_attribute(&git_cell, "foreground", 5);
+    column.add_attribute(&status_cell, "icon-name", 2);
     project_tree.append_column(&column);
 
+    let new_file_item = widgets::MenuItem::new_with_label("New File").unwrap();
+    let new_folder_item = widgets::MenuItem::new_with_label("New Folder").unwrap();
+    let duplicate_item = widgets::MenuItem::new_with_label("Duplicate").unwrap();
+    let copy_path_item = widgets::MenuItem::new_with_label("Copy Path").unwrap();
+    let copy_relative_path_item = widgets::MenuItem::new_with_label("Copy Relative Path").unwrap();
+    let terminal_item = widgets::MenuItem::new_with_label("Open in Terminal").unwrap();
+    let rename_item = widgets::MenuItem::new_with_label("Rename").unwrap();
+    let undo_delete_item = widgets::MenuItem::new_with_label("Undo Delete").unwrap();
+
+    let tree_menu = widgets::Menu::new().unwrap();
+    tree_menu.append(&new_file_item);
+    tree_menu.append(&new_folder_item);
+    tree_menu.append(&duplicate_item);
+    tree_menu.append(&copy_path_item);
+    tree_menu.append(&copy_relative_path_item);
+    tree_menu.append(&terminal_item);
+    tree_menu.append(&rename_item);
+    tree_menu.append(&undo_delete_item);
+    tree_menu.show_all();
+
+    let filter_entry = widgets::SearchEntry::new().unwrap();
+    filter_entry.set_placeholder_text("Filter");
+
     let left_pane = widgets::Box::new(gtk::Orientation::Vertical, 0).unwrap();
     left_pane.pack_start(&project_buttons, false, true, 0);
+    left_pane.pack_start(&filter_entry, false, true, 0);
     left_pane.pack_start(&scroll_pane, true, true, 0);
 
     // create the right pane
@@ -67,6 +177,8 @@ fn gui_main(pty: &mut widgets::VtePty, read_fd: i32, write_fd: i32, pid: i32) {
     let save_button = widgets::Button::new_with_label("Save").unwrap();
     let undo_button = widgets::Button::new_with_label("Undo").unwrap();
     let redo_button = widgets::Button::new_with_label("Redo").unwrap();
+    let prev_error_button = widgets::Button::new_with_label("< Error").unwrap();
+    let next_error_button = widgets::Button::new_with_label("Error >").unwrap();
     let font_dec_button = widgets::Button::new_with_label("Font -").unwrap();
     let font_inc_button = widgets::Button::new_with_label("Font +").unwrap();
     let easy_mode_button = widgets::ToggleButton::new_with_label("Easy Mode").unwrap();
@@ -77,22 +189,33 @@ fn gui_main(pty: &mut widgets::VtePty, read_fd: i32, write_fd: i32, pid: i32) {
     editor_buttons.add(&save_button);
     editor_buttons.add(&undo_button);
     editor_buttons.add(&redo_button);
+    editor_buttons.add(&prev_error_button);
+    editor_buttons.add(&next_error_button);
     editor_buttons.add(&font_dec_button);
     editor_buttons.add(&font_inc_button);
     editor_buttons.add(&easy_mode_button);
     editor_buttons.pack_start(&editor_separator, true, false, 0);
     editor_buttons.add(&close_button);
 
+    let mut buffer_notebook = widgets::Notebook::new().unwrap();
+    buffer_notebook.set_scrollable(true);
+    buffer_notebook.set_show_border(false);
+    buffer_notebook.set_can_focus(false);
+
     let mut editor_term = widgets::VteTerminal::new().unwrap();
     editor_term.set_pty(pty);
     editor_term.watch_child(pid);
-    editor_term.set_size_request(-1, (utils::EDITOR_HEIGHT_PCT * (utils::WINDOW_HEIGHT as f32)) as i32);
+
+    let editor_pane = widgets::Box::new(gtk::Orientation::Vertical, 0).unwrap();
+    editor_pane.pack_start(&buffer_notebook, false, true, 0);
+    editor_pane.pack_start(&editor_term, true, true, 0);
 
     let run_button = widgets::Button::new_with_label("Run").unwrap();
     let build_button = widgets::Button::new_with_label("Build").unwrap();
     let test_button = widgets::Button::new_with_label("Test").unwrap();
     let clean_button = widgets::Button::new_with_label("Clean").unwrap();
     let stop_button = widgets::Button::new_with_label("Stop").unwrap();
+    let profile_combo = widgets::ComboBoxText::new().unwrap();
 
     let mut build_buttons = widgets::Box::new(gtk::Orientation::Horizontal, 0).unwrap();
     build_buttons.add(&run_button);
@@ -100,15 +223,39 @@ fn gui_main(pty: &mut widgets::VtePty, read_fd: i32, write_fd: i32, pid: i32) {
     build_buttons.add(&test_button);
     build_buttons.add(&clean_button);
     build_buttons.add(&stop_button);
+    build_buttons.add(&profile_combo);
 
     let mut build_terms = widgets::Stack::new().unwrap();
 
+    let diagnostics_tree = widgets::TreeView::new().unwrap();
+    let diagnostics_selection = diagnostics_tree.get_selection().unwrap();
+    let diagnostics_column_types = [glib::Type::String, glib::Type::String, glib::Type::String, glib::Type::String];
+    let diagnostics_store = widgets::TreeStore::new(&diagnostics_column_types).unwrap();
+    let diagnostics_model = diagnostics_store.get_model().unwrap();
+    diagnostics_tree.set_model(&diagnostics_model);
+    diagnostics_tree.set_headers_visible(false);
+    diagnostics_tree.set_can_focus(false);
+
+    let diagnostics_column = widgets::TreeViewColumn::new().unwrap();
+    let diagnostics_cell = widgets::CellRendererText::new().unwrap();
+    diagnostics_column.pack_start(&diagnostics_cell, true);
+    diagnostics_column.add_attribute(&diagnostics_cell, "text", 0);
+    diagnostics_tree.append_column(&diagnostics_column);
+
+    let diagnostics_pane = widgets::ScrolledWindow::new(None, None).unwrap();
+    diagnostics_pane.add(&diagnostics_tree);
+
+    let build_output = widgets::Paned::new(gtk::Orientation::Horizontal).unwrap();
+    build_output.add1(&build_terms);
+    build_output.add2(&diagnostics_pane);
+    build_output.set_position(utils::DIAGNOSTICS_POSITION);
+
     let build_pane = widgets::Box::new(gtk::Orientation::Vertical, 0).unwrap();
     build_pane.pack_start(&build_buttons, false, true, 0);
-    build_pane.pack_start(&build_terms, true, true, 0);
+    build_pane.pack_start(&build_output, true, true, 0);
 
     let resizer = widgets::Paned::new(gtk::Orientation::Vertical).unwrap();
-    resizer.add1(&editor_term);
+    resizer.add1(&editor_pane);
     resizer.add2(&build_pane);
 
     let right_pane = widgets::Box::new(gtk::Orientation::Vertical, 0).unwrap();
@@ -121,18 +268,11 @@ fn gui_main(pty: &mut widgets::VtePty, read_fd: i32, write_fd: i32, pid: i32) {
                         option_env!("CARGO_PKG_VERSION_MAJOR").unwrap(),
                         option_env!("CARGO_PKG_VERSION_MINOR").unwrap(),
                         option_env!("CARGO_PKG_VERSION_PATCH").unwrap());
-    let mut quit_app = false;
     let window = widgets::Window::new(gtk::WindowType::TopLevel).unwrap();
     window.set_title(title.as_ref());
     window.set_window_position(gtk::WindowPosition::Center);
     window.set_default_size(utils::WINDOW_WIDTH, utils::WINDOW_HEIGHT);
-    window.connect(signals::DeleteEvent::new(&mut |_| {
-        ffi::send_message(write_fd, "qall!");
-        ffi::close_fd(read_fd);
-        ffi::close_fd(write_fd);
-        quit_app = true;
-        true
-    }));
+    let mut client = nvim::Client::new(read_fd, write_fd);
 
     let window_pane = widgets::Paned::new(gtk::Orientation::Horizontal).unwrap();
     window_pane.add1(&left_pane);
@@ -141,6 +281,107 @@ fn gui_main(pty: &mut widgets::VtePty, read_fd: i32, write_fd: i32, pid: i32) {
     window.add(&window_pane);
     window.show_all();
 
+    // create the go to file palette
+
+    let palette_entry = widgets::SearchEntry::new().unwrap();
+    palette_entry.set_placeholder_text("Go to File");
+
+    let mut palette_tree = widgets::TreeView::new().unwrap();
+    let palette_selection = palette_tree.get_selection().unwrap();
+    let palette_column_types = [glib::Type::String, glib::Type::String];
+    let palette_store = widgets::TreeStore::new(&palette_column_types).unwrap();
+    let palette_model = palette_store.get_model().unwrap();
+    palette_tree.set_model(&palette_model);
+    palette_tree.set_headers_visible(false);
+    palette_tree.set_can_focus(false);
+
+    let palette_column = widgets::TreeViewColumn::new().unwrap();
+    let palette_cell = widgets::CellRendererText::new().unwrap();
+    palette_column.pack_start(&palette_cell, true);
+    palette_column.add_attribute(&palette_cell, "text", 0);
+    palette_tree.append_column(&palette_column);
+
+    let palette_scroll_pane = widgets::ScrolledWindow::new(None, None).unwrap();
+    palette_scroll_pane.add(&palette_tree);
+
+    let palette_pane = widgets::Box::new(gtk::Orientation::Vertical, 0).unwrap();
+    palette_pane.pack_start(&palette_entry, false, true, 0);
+    palette_pane.pack_start(&palette_scroll_pane, true, true, 0);
+
+    let palette_window = widgets::Window::new(gtk::WindowType::TopLevel).unwrap();
+    palette_window.set_title("Go to File");
+    palette_window.set_transient_for(&window);
+    palette_window.set_modal(true);
+    palette_window.set_window_position(gtk::WindowPosition::CenterOnParent);
+    palette_window.set_default_size(utils::PALETTE_WIDTH, utils::PALETTE_HEIGHT);
+    palette_window.add(&palette_pane);
+
+    // create the find in files panel
+
+    let search_entry = widgets::SearchEntry::new().unwrap();
+    search_entry.set_placeholder_text("Find");
+    let search_button = widgets::Button::new_with_label("Find").unwrap();
+    let replace_entry = widgets::Entry::new().unwrap();
+    replace_entry.set_placeholder_text("Replace");
+    let replace_button = widgets::Button::new_with_label("Replace Checked").unwrap();
+    let regex_check = widgets::CheckButton::new_with_label("Regex").unwrap();
+    let case_check = widgets::CheckButton::new_with_label("Match Case").unwrap();
+    let word_check = widgets::CheckButton::new_with_label("Whole Word").unwrap();
+    let include_entry = widgets::Entry::new().unwrap();
+    include_entry.set_placeholder_text("Include, like *.rs, src/");
+    let exclude_entry = widgets::Entry::new().unwrap();
+    exclude_entry.set_placeholder_text("Exclude");
+
+    let search_row = widgets::Box::new(gtk::Orientation::Horizontal, 0).unwrap();
+    search_row.pack_start(&search_entry, true, true, 0);
+    search_row.pack_start(&search_button, false, true, 0);
+    let replace_row = widgets::Box::new(gtk::Orientation::Horizontal, 0).unwrap();
+    replace_row.pack_start(&replace_entry, true, true, 0);
+    replace_row.pack_start(&replace_button, false, true, 0);
+    let options_row = widgets::Box::new(gtk::Orientation::Horizontal, 0).unwrap();
+    options_row.add(&regex_check);
+    options_row.add(&case_check);
+    options_row.add(&word_check);
+    options_row.pack_start(&include_entry, true, true, 0);
+    options_row.pack_start(&exclude_entry, true, true, 0);
+
+    let search_tree = widgets::TreeView::new().unwrap();
+    let search_selection = search_tree.get_selection().unwrap();
+    let search_column_types = [glib::Type::String, glib::Type::String, glib::Type::String,
+                               glib::Type::String, glib::Type::Bool];
+    let search_store = widgets::TreeStore::new(&search_column_types).unwrap();
+    let search_model = search_store.get_model().unwrap();
+    search_tree.set_model(&search_model);
+    search_tree.set_headers_visible(false);
+    search_tree.set_can_focus(false);
+
+    let search_column = widgets::TreeViewColumn::new().unwrap();
+    let search_check_cell = widgets::CellRendererToggle::new().unwrap();
+    let search_cell = widgets::CellRendererText::new().unwrap();
+    search_column.pack_start(&search_check_cell, false);
+    search_column.pack_start(&search_cell, true);
+    search_column.add_attribute(&search_check_cell, "active", 4);
+    search_column.add_attribute(&search_cell, "markup", 0);
+    search_tree.append_column(&search_column);
+
+    let search_scroll_pane = widgets::ScrolledWindow::new(None, None).unwrap();
+    search_scroll_pane.add(&search_tree);
+    let search_label = widgets::Label::new("").unwrap();
+
+    let search_pane = widgets::Box::new(gtk::Orientation::Vertical, 0).unwrap();
+    search_pane.pack_start(&search_row, false, true, 0);
+    search_pane.pack_start(&replace_row, false, true, 0);
+    search_pane.pack_start(&options_row, false, true, 0);
+    search_pane.pack_start(&search_scroll_pane, true, true, 0);
+    search_pane.pack_start(&search_label, false, true, 0);
+
+    let search_window = widgets::Window::new(gtk::WindowType::TopLevel).unwrap();
+    search_window.set_title("Find in Files");
+    search_window.set_transient_for(&window);
+    search_window.set_window_position(gtk::WindowPosition::CenterOnParent);
+    search_window.set_default_size(utils::SEARCH_WIDTH, utils::SEARCH_HEIGHT);
+    search_window.add(&search_pane);
+
     // set the shortcuts
 
     let mut shortcuts = HashMap::new();
@@ -150,6 +391,8 @@ fn gui_main(pty: &mut widgets::VtePty, read_fd: i32, write_fd: i32, pid: i32) {
     if let Some(key) = settings.keys.import { shortcuts.insert(key, &import_button); }
     if let Some(key) = settings.keys.rename { shortcuts.insert(key, &rename_button); }
     if let Some(key) = settings.keys.remove { shortcuts.insert(key, &remove_button); }
+    if let Some(key) = settings.keys.go_to_file { shortcuts.insert(key, &go_to_file_button); }
+    if let Some(key) = settings.keys.find_in_files { shortcuts.insert(key, &find_button); }
 
     if let Some(key) = settings.keys.run { shortcuts.insert(key, &run_button); }
     if let Some(key) = settings.keys.build { shortcuts.insert(key, &build_button); }
@@ -160,6 +403,8 @@ fn gui_main(pty: &mut widgets::VtePty, read_fd: i32, write_fd: i32, pid: i32) {
     if let Some(key) = settings.keys.save { shortcuts.insert(key, &save_button); }
     if let Some(key) = settings.keys.undo { shortcuts.insert(key, &undo_button); }
     if let Some(key) = settings.keys.redo { shortcuts.insert(key, &redo_button); }
+    if let Some(key) = settings.keys.prev_error { shortcuts.insert(key, &prev_error_button); }
+    if let Some(key) = settings.keys.next_error { shortcuts.insert(key, &next_error_button); }
     if let Some(key) = settings.keys.font_dec { shortcuts.insert(key, &font_dec_button); }
     if let Some(key) = settings.keys.font_inc { shortcuts.insert(key, &font_inc_button); }
     if let Some(key) = settings.keys.close { shortcuts.insert(key, &close_button); }
@@ -184,65 +429,301 @@ fn gui_main(pty: &mut widgets::VtePty, read_fd: i32, write_fd: i32, pid: i32) {
 
     // populate the project tree
 
+    let (build_notifier, build_receiver, build_fd) = ffi::new_notifier();
+    let (watch_notifier, watch_receiver, watch_fd) = ffi::new_notifier();
+    let (git_notifier, git_receiver, git_fd) = ffi::new_notifier();
+    let (index_notifier, index_receiver, index_fd) = ffi::new_notifier();
+    let (search_notifier, search_receiver, search_fd) = ffi::new_notifier();
+    let (tab_notifier, tab_receiver, tab_fd) = ffi::new_notifier();
+
     let mut state = ::utils::State{
+        settings: ::utils::read_settings(),
         projects: HashSet::new(),
         expansions: HashSet::new(),
         builders: HashMap::new(),
+        visible_builder: None,
+        build_notifier: build_notifier,
+        watcher: ::watcher::start(watch_notifier),
+        git_statuses: HashMap::new(),
+        git_pending: HashSet::new(),
+        git_stale: HashSet::new(),
+        git_notifier: git_notifier,
+        trashed: Vec::new(),
+        file_index: HashMap::new(),
+        index_notifier: index_notifier,
+        index_generation: 0,
+        index_pending: HashMap::new(),
+        recent_files: Vec::new(),
+        project_settings: RefCell::new(HashMap::new()),
+        window_layout: None,
+        palette_store: &palette_store,
+        palette_model: &palette_model,
+        palette_selection: &palette_selection,
+        search: None,
+        search_notifier: search_notifier,
+        search_store: &search_store,
+        search_model: &search_model,
+        search_selection: &search_selection,
+        search_label: &search_label,
+        buffer_tabs: Vec::new(),
+        tab_notifier: tab_notifier,
         selection: None,
         easy_mode: true,
+        show_ignored: false,
         font_size: 12,
+        build_profiles: HashMap::new(),
         window: &window,
         tree_model: &model,
+        tree_filter: &tree_filter,
+        filter_model: &filter_model,
+        filter_query: String::new(),
         tree_store: &store,
         tree_selection: &selection,
+        diagnostics_store: &diagnostics_store,
+        diagnostics_model: &diagnostics_model,
+        diagnostics_selection: &diagnostics_selection,
+        profile_combo: &profile_combo,
         rename_button: &rename_button,
         remove_button: &remove_button,
-        is_refreshing_tree: false
+        is_refreshing_tree: false,
+        is_refreshing_profiles: false,
+        is_refreshing_tabs: false
     };
 
     ::utils::read_prefs(&mut state);
+    // a session's layout wins over the last one used by any set of projects
+    let session = ::session::read_session(&state);
+    if let Some(layout) = session.as_ref().and_then(|session| session.layout.clone()) {
+        state.window_layout = Some(layout);
+    }
+    ::ui::apply_window_layout(state.window_layout.as_ref(), &window, &window_pane, &resizer);
+    if let Some(ref session) = session {
+        ::session::apply_session(&mut state, session);
+    }
     ::ui::update_project_tree(&mut state, &mut project_tree);
-    ::projects::set_selection(&mut state, &mut project_tree, write_fd);
+    state.watcher.watch_all(state.show_ignored);
+    for path_str in state.projects.iter() {
+        ::projects::watch_project(&state, path_str);
+    }
+    ::git::refresh_all(&mut state);
+    ::index::index_all(&mut state);
+    ::session::start_sessions(&state, &mut client);
+    if !::session::restore_buffers(&state, &mut client) {
+        ::projects::set_selection(&mut state, &mut client);
+    }
 
     easy_mode_button.set_active(state.easy_mode);
-    ::ffi::send_message(write_fd, if state.easy_mode { "set im" } else { "set noim" });
+    show_ignored_button.set_active(state.show_ignored);
+    client.set_bool_option("insertmode", state.easy_mode);
     editor_term.set_font_size(state.font_size);
     editor_term.grab_focus();
 
     // connect to the signals
 
+    window.connect(signals::DeleteEvent::new(&mut |_| {
+        if ::projects::confirm_unsaved_buffers(&state, &mut client, None) {
+            client.command("qall!");
+            gtk::main_quit();
+        }
+        true
+    }));
     new_button.connect(signals::Clicked::new(&mut || {
-        ::projects::new_project(&mut state, &mut project_tree);
+        ::projects::new_project(&mut state, &mut project_tree, &mut client);
     }));
     import_button.connect(signals::Clicked::new(&mut || {
-        ::projects::import_project(&mut state, &mut project_tree);
+        ::projects::import_project(&mut state, &mut project_tree, &mut client);
     }));
     rename_button.connect(signals::Clicked::new(&mut || {
-        ::projects::rename_file(&mut state, write_fd);
+        ::projects::rename_file(&mut state, &mut client);
     }));
     remove_button.connect(signals::Clicked::new(&mut || {
-        ::projects::remove_item(&mut state, &mut project_tree, write_fd);
+        ::projects::remove_item(&mut state, &mut project_tree, &mut client);
+    }));
+    go_to_file_button.connect(signals::Clicked::new(&mut || {
+        ::palette::show_palette(&state, &palette_window, &palette_entry);
+    }));
+    show_ignored_button.connect(signals::Clicked::new(&mut || {
+        state.show_ignored = show_ignored_button.get_active();
+        state.watcher.watch_all(state.show_ignored);
+        ::utils::write_prefs(&state);
+        ::ui::update_project_tree(&mut state, &mut project_tree);
     }));
     selection.connect(signals::Changed::new(&mut || {
-        ::projects::set_selection(&mut state, &mut project_tree, write_fd);
+        ::projects::set_selection(&mut state, &mut client);
+    }));
+    // right clicks don't move the selection, so select the row under the pointer first
+    // and leave the menu closed when there isn't one
+    let menu_tree_ptr = project_tree.unwrap_widget() as ui::WidgetPtr;
+    project_tree.connect(signals::ButtonReleaseEvent::new(&mut |event| {
+        let button = unsafe { (*event).button };
+        let time = unsafe { (*event).time };
+        let (x, y) = unsafe { ((*event).x as i32, (*event).y as i32) };
+        if button == 3 && ui::select_tree_row_at_pos(menu_tree_ptr, x, y) {
+            if let Some(path_str) = ::utils::get_selected_path(&state) {
+                duplicate_item.set_sensitive(!state.projects.contains(&path_str));
+                rename_item.set_sensitive(::ui::can_rename(&state, &path_str));
+                undo_delete_item.set_sensitive(state.trashed.len() > 0);
+                tree_menu.popup_easy(button, time);
+                return true;
+            }
+        }
+        false
+    }));
+    new_file_item.connect(signals::Activate::new(&mut || {
+        ::projects::new_file(&mut state, &mut client);
+    }));
+    new_folder_item.connect(signals::Activate::new(&mut || {
+        ::projects::new_folder(&mut state);
+    }));
+    duplicate_item.connect(signals::Activate::new(&mut || {
+        ::projects::duplicate_item(&mut state, &mut client);
+    }));
+    copy_path_item.connect(signals::Activate::new(&mut || {
+        ::projects::copy_selected_path(&state, false);
+    }));
+    copy_relative_path_item.connect(signals::Activate::new(&mut || {
+        ::projects::copy_selected_path(&state, true);
+    }));
+    terminal_item.connect(signals::Activate::new(&mut || {
+        ::projects::open_terminal(&state);
+    }));
+    rename_item.connect(signals::Activate::new(&mut || {
+        ::projects::rename_file(&mut state, &mut client);
+    }));
+    undo_delete_item.connect(signals::Activate::new(&mut || {
+        ::projects::undo_delete(&mut state, &mut client);
+    }));
+
+    filter_entry.connect(signals::Changed::new(&mut || {
+        if let Some(query) = filter_entry.get_text() {
+            ::ui::filter_tree(&mut state, &mut project_tree, query.as_ref());
+        }
+    }));
+
+    palette_window.connect(signals::DeleteEvent::new(&mut |_| {
+        palette_window.hide();
+        true
+    }));
+    palette_window.connect(signals::KeyPressEvent::new(&mut |key| {
+        let keyval = unsafe { (*key).keyval };
+        if let Some(name_str) = gdk::keyval_name(keyval) {
+            match name_str.as_ref() {
+                "Escape" => palette_window.hide(),
+                "Up" => ::palette::move_selection(&state, &mut palette_tree, false),
+                "Down" => ::palette::move_selection(&state, &mut palette_tree, true),
+                "Return" | "KP_Enter" => ::palette::open_selected(&mut state, &mut client, &palette_window),
+                _ => return false
+            }
+            return true;
+        }
+        false
+    }));
+    palette_entry.connect(signals::Changed::new(&mut || {
+        if let Some(query) = palette_entry.get_text() {
+            ::palette::update_results(&state, query.as_ref());
+        }
+    }));
+    palette_tree.connect(signals::ButtonReleaseEvent::new(&mut |event| {
+        let button = unsafe { (*event).button };
+        if button == 1 {
+            ::palette::open_selected(&mut state, &mut client, &palette_window);
+            return true;
+        }
+        false
+    }));
+
+    find_button.connect(signals::Clicked::new(&mut || {
+        search_window.show_all();
+        search_entry.grab_focus();
+    }));
+    search_window.connect(signals::DeleteEvent::new(&mut |_| {
+        search_window.hide();
+        true
+    }));
+    search_window.connect(signals::KeyPressEvent::new(&mut |key| {
+        let keyval = unsafe { (*key).keyval };
+        if let Some(name_str) = gdk::keyval_name(keyval) {
+            if name_str == "Escape" {
+                search_window.hide();
+                return true;
+            }
+        }
+        false
+    }));
+    search_button.connect(signals::Clicked::new(&mut || {
+        let options = ::search::SearchOptions {
+            query: search_entry.get_text().unwrap_or(String::new()),
+            is_regex: regex_check.get_active(),
+            is_case_sensitive: case_check.get_active(),
+            is_whole_word: word_check.get_active(),
+            include: ::search::split_patterns(include_entry.get_text().unwrap_or(String::new()).as_ref()),
+            exclude: ::search::split_patterns(exclude_entry.get_text().unwrap_or(String::new()).as_ref())
+        };
+        ::search::start_search(&mut state, options);
+    }));
+    search_entry.connect(signals::Activate::new(&mut || {
+        search_button.clicked();
+    }));
+    replace_entry.connect(signals::Changed::new(&mut || {
+        let replacement = replace_entry.get_text().unwrap_or(String::new());
+        ::search::update_preview(&state, if replacement.len() > 0 { Some(replacement.as_ref()) } else { None });
+    }));
+    replace_button.connect(signals::Clicked::new(&mut || {
+        let replacement = replace_entry.get_text().unwrap_or(String::new());
+        ::search::replace_checked(&mut state, &mut client, replacement.as_ref());
+    }));
+    search_selection.connect(signals::Changed::new(&mut || {
+        ::search::open_match(&state, &mut client);
+    }));
+    search::connect_cell_toggled(search_check_cell.unwrap_widget() as ui::WidgetPtr, Box::new(|path_str| {
+        ::search::toggle_row(&state, path_str.as_ref());
+    }));
+
+    // switch buffers by clicking their tabs, and drag the tabs to reorder them
+
+    let notebook_ptr = buffer_notebook.unwrap_widget() as ui::WidgetPtr;
+    tabs::connect_page_signal(notebook_ptr, "switch-page", Box::new(|_, page_num| {
+        ::tabs::switch_tab(&state, &mut client, page_num);
+    }));
+    tabs::connect_page_signal(notebook_ptr, "page-reordered", Box::new(|page_ptr, page_num| {
+        ::tabs::move_tab(&mut state, page_ptr, page_num);
+    }));
+
+    // drag rows to move them, or drop files from elsewhere to copy them in
+
+    let tree_ptr = project_tree.unwrap_widget() as ui::WidgetPtr;
+    ui::enable_tree_drag_and_drop(tree_ptr);
+    ui::connect_drag_data_get(tree_ptr, Box::new(|| {
+        ::projects::get_selected_uris(&state)
+    }));
+    ui::connect_drag_data_received(tree_ptr, Box::new(|x, y, uris| {
+        let target_str = ui::get_tree_drop_value(tree_ptr, x, y, 1);
+        ::projects::drop_uris(&mut state, &mut client, target_str, uris)
     }));
     project_tree.connect(signals::RowCollapsed::new(&mut |iter_raw, _| {
-        let iter = widgets::TreeIter::wrap_pointer(iter_raw);
+        let iter = ::ui::get_store_iter(&state, &widgets::TreeIter::wrap_pointer(iter_raw));
         ::projects::remove_expansion(&mut state, &iter);
     }));
     project_tree.connect(signals::RowExpanded::new(&mut |iter_raw, _| {
-        let iter = widgets::TreeIter::wrap_pointer(iter_raw);
+        let iter = ::ui::get_store_iter(&state, &widgets::TreeIter::wrap_pointer(iter_raw));
+        ::ui::load_children(&mut state, &iter);
         ::projects::add_expansion(&mut state, &iter);
     }));
 
     save_button.connect(signals::Clicked::new(&mut || {
-        ::ffi::send_message(write_fd, "w");
+        client.write();
     }));
     undo_button.connect(signals::Clicked::new(&mut || {
-        ::ffi::send_message(write_fd, "undo");
+        client.undo();
     }));
     redo_button.connect(signals::Clicked::new(&mut || {
-        ::ffi::send_message(write_fd, "redo");
+        client.redo();
+    }));
+    prev_error_button.connect(signals::Clicked::new(&mut || {
+        client.previous_error();
+    }));
+    next_error_button.connect(signals::Clicked::new(&mut || {
+        client.next_error();
     }));
     font_dec_button.connect(signals::Clicked::new(&mut || {
         if state.font_size > ::utils::MIN_FONT_SIZE {
@@ -263,73 +744,163 @@ fn gui_main(pty: &mut widgets::VtePty, read_fd: i32, write_fd: i32, pid: i32) {
     easy_mode_button.connect(signals::Clicked::new(&mut || {
         state.easy_mode = easy_mode_button.get_active();
         ::utils::write_prefs(&state);
-        ::ffi::send_message(write_fd, if state.easy_mode { "set im" } else { "set noim" });
+        client.set_bool_option("insertmode", state.easy_mode);
     }));
     close_button.connect(signals::Clicked::new(&mut || {
-        ::ffi::send_message(write_fd, "bd");
+        client.close_buffer();
     }));
 
     run_button.connect(signals::Clicked::new(&mut || {
-        ::builders::stop_builder(&mut state);
-        ::builders::run_builder(&mut state, &["cargo", "run"]);
+        ::builders::run_builder(&mut state, &mut client, "run");
     }));
     build_button.connect(signals::Clicked::new(&mut || {
-        ::builders::stop_builder(&mut state);
-        ::builders::run_builder(&mut state, &["cargo", "build", "--release"]);
+        ::builders::run_builder(&mut state, &mut client, "build");
     }));
     test_button.connect(signals::Clicked::new(&mut || {
-        ::builders::stop_builder(&mut state);
-        ::builders::run_builder(&mut state, &["cargo", "test"]);
+        ::builders::run_builder(&mut state, &mut client, "test");
     }));
     clean_button.connect(signals::Clicked::new(&mut || {
-        ::builders::stop_builder(&mut state);
-        ::builders::run_builder(&mut state, &["cargo", "clean"]);
+        ::builders::run_builder(&mut state, &mut client, "clean");
     }));
     stop_button.connect(signals::Clicked::new(&mut || {
         ::builders::stop_builder(&mut state);
     }));
+    profile_combo.connect(signals::Changed::new(&mut || {
+        ::builders::select_profile(&mut state);
+    }));
+    diagnostics_selection.connect(signals::Changed::new(&mut || {
+        ::builders::open_diagnostic(&mut state, &mut client);
+    }));
 
     // listen for events
 
-    ffi::send_message(write_fd, "au BufEnter * call rpcnotify(1, 'bufenter', fnamemodify(bufname(''), ':p'))");
-    ffi::send_message(write_fd, "au VimLeave * call rpcnotify(1, 'vimleave')");
+    client.command("au BufEnter * call rpcnotify(1, 'bufenter', fnamemodify(bufname(''), ':p'))");
+    client.command("au VimLeave * call rpcnotify(1, 'vimleave')");
+    client.command("au BufWritePost * call rpcnotify(1, 'bufwrite', expand('<afile>:p'))");
+    client.command("au BufAdd * call rpcnotify(1, 'bufadd')");
+    client.command("au BufDelete * call rpcnotify(1, 'bufdelete')");
+    client.command("au BufModifiedSet * call rpcnotify(1, 'bufmodified')");
+    client.command("au BufWritePost *.rs if get(b:, 'soak_rustfmt', 0) | \
+                    silent execute '!rustfmt ' . shellescape(expand('%:p')) | silent edit | endif");
 
-    // make read_fd non-blocking so we can check it while also checking for GUI events
+    // respond to messages from nvim whenever the pipe becomes readable
 
-    ffi::set_non_blocking(read_fd);
+    let watch_id = ffi::add_fd_watch(read_fd, Box::new(|| {
+        handle_nvim_messages(&mut state, &mut client, &mut project_tree,
+                             &mut build_buttons, &mut build_terms, &mut buffer_notebook);
 
-    // loop over GUI events and respond to messages from nvim
+        // the watch itself is removed once gtk::main returns
+        if !client.is_connected() {
+            gtk::main_quit();
+        }
 
-    loop {
-        gtk::main_iteration_do(false);
+        true
+    }));
 
-        if let Some(recv_arr) = ffi::recv_message(read_fd) {
-            if let Some(neovim::Object::String(event_name)) = recv_arr.get(1) {
-                match event_name.as_ref() {
-                    "bufenter" => {
-                        if let Some(neovim::Object::Array(event_args)) = recv_arr.get(2) {
-                            if let Some(neovim::Object::String(path_str)) = event_args.get(0) {
-                                state.selection = Some(path_str);
-                                ::utils::write_prefs(&state);
-                            }
-                        }
+    // respond to messages that arrived while waiting on a response from nvim
+
+    let wake_fd = client.wake_fd();
+    let wake_watch_id = ffi::add_fd_watch(wake_fd, Box::new(|| {
+        ffi::drain_fd(wake_fd);
+        handle_nvim_messages(&mut state, &mut client, &mut project_tree,
+                             &mut build_buttons, &mut build_terms, &mut buffer_notebook);
+        true
+    }));
+
+    // respond to events from the build threads
+
+    let build_watch_id = ffi::add_fd_watch(build_fd, Box::new(|| {
+        ffi::drain_fd(build_fd);
+        while let Ok(event) = build_receiver.try_recv() {
+            ::builders::handle_event(&mut state, &mut client, event);
+        }
+        true
+    }));
+
+    // respond to changes in the project directories
+
+    let files_watch_id = ffi::add_fd_watch(watch_fd, Box::new(|| {
+        ffi::drain_fd(watch_fd);
+        while let Ok(changes) = watch_receiver.try_recv() {
+            let mut paths = Vec::new();
+            for change in changes.iter() {
+                match *change {
+                    ::watcher::Change::Created(ref path) | ::watcher::Change::Removed(ref path) => {
+                        paths.push(path.clone());
+                    },
+                    ::watcher::Change::Moved(ref from, ref to) => {
+                        paths.push(from.clone());
+                        paths.push(to.clone());
                     },
-                    "vimleave" => { quit_app = true; }
-                    _ => (),
+                    ::watcher::Change::Modified(_) => {},
+                    ::watcher::Change::Rescan => ::git::refresh_all(&mut state)
                 }
             }
-            ::ui::update_project_tree(&mut state, &mut project_tree);
-            ::builders::show_builder(&mut state, &mut build_buttons, &mut build_terms);
-            ::builders::set_builders_font_size(&mut state);
+            ::utils::apply_settings_changes(&state, changes.as_ref());
+            ::git::refresh_paths(&mut state, paths.as_ref());
+            ::index::apply_changes(&mut state, changes.as_ref());
+            ::ui::apply_changes(&mut state, &mut project_tree, changes);
         }
+        true
+    }));
+
+    // respond to git status results
 
-        if quit_app {
-            break;
+    let git_watch_id = ffi::add_fd_watch(git_fd, Box::new(|| {
+        ffi::drain_fd(git_fd);
+        while let Ok(update) = git_receiver.try_recv() {
+            ::git::handle_update(&mut state, update);
         }
+        true
+    }));
 
-        thread::sleep_ms(10);
-    }
+    // respond to file index results
+
+    let index_watch_id = ffi::add_fd_watch(index_fd, Box::new(|| {
+        ffi::drain_fd(index_fd);
+        while let Ok(update) = index_receiver.try_recv() {
+            ::index::handle_update(&mut state, update);
+        }
+        ::ui::update_filter(&mut state, &mut project_tree);
+        true
+    }));
+
+    // respond to find in files results
+
+    let search_watch_id = ffi::add_fd_watch(search_fd, Box::new(|| {
+        ffi::drain_fd(search_fd);
+        let replacement = replace_entry.get_text().unwrap_or(String::new());
+        while let Ok(event) = search_receiver.try_recv() {
+            ::search::handle_event(&mut state, event, if replacement.len() > 0 { Some(replacement.as_ref()) } else { None });
+        }
+        true
+    }));
+
+    // respond to tabs being closed
+
+    let tab_watch_id = ffi::add_fd_watch(tab_fd, Box::new(|| {
+        ffi::drain_fd(tab_fd);
+        while let Ok(event) = tab_receiver.try_recv() {
+            ::tabs::handle_event(&state, &mut client, event);
+        }
+        true
+    }));
 
+    gtk::main();
+
+    state.window_layout = Some(::ui::get_window_layout(state.window_layout.as_ref(), &window, &window_pane, &resizer));
+    ::utils::write_prefs(&state);
+    ::session::write_session(&state);
+
+    ffi::remove_fd_watch(watch_id);
+    ffi::remove_fd_watch(wake_watch_id);
+    ffi::remove_fd_watch(build_watch_id);
+    ffi::remove_fd_watch(files_watch_id);
+    ffi::remove_fd_watch(git_watch_id);
+    ffi::remove_fd_watch(index_watch_id);
+    ffi::remove_fd_watch(search_watch_id);
+    ffi::remove_fd_watch(tab_watch_id);
+    client.close();
     ::builders::stop_builders(&mut state);
 }
 
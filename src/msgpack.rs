@@ -0,0 +1,277 @@
+use std::mem;
+use std::str;
+
+// returns the size of an object's header, the size of its payload, and how many child objects follow it
+fn read_header(buf: &[u8]) -> Result<Option<(usize, usize, usize)>, u8> {
+    if buf.len() == 0 {
+        return Ok(None);
+    }
+
+    let b = buf[0];
+    let (header_len, len_bytes) = match b {
+        0xc4 | 0xc7 | 0xd9 => (2, 1),
+        0xc5 | 0xc8 | 0xda | 0xdc | 0xde => (3, 2),
+        0xc6 | 0xc9 | 0xdb | 0xdd | 0xdf => (5, 4),
+        _ => (1, 0)
+    };
+    if buf.len() < header_len {
+        return Ok(None);
+    }
+
+    let mut n = 0usize;
+    for i in 1..(len_bytes + 1) {
+        n = (n << 8) | (buf[i] as usize);
+    }
+
+    Ok(Some(match b {
+        0x00...0x7f | 0xe0...0xff | 0xc0 | 0xc2 | 0xc3 => (1, 0, 0),
+        0x80...0x8f => (1, 0, ((b & 0x0f) as usize) * 2),
+        0x90...0x9f => (1, 0, (b & 0x0f) as usize),
+        0xa0...0xbf => (1, (b & 0x1f) as usize, 0),
+        0xc4...0xc6 | 0xd9...0xdb => (header_len, n, 0),
+        // ext types have an extra type byte after the length
+        0xc7...0xc9 => (header_len + 1, n, 0),
+        0xca => (1, 4, 0),
+        0xcb => (1, 8, 0),
+        0xcc | 0xd0 => (1, 1, 0),
+        0xcd | 0xd1 => (1, 2, 0),
+        0xce | 0xd2 => (1, 4, 0),
+        0xcf | 0xd3 => (1, 8, 0),
+        0xd4 => (2, 1, 0),
+        0xd5 => (2, 2, 0),
+        0xd6 => (2, 4, 0),
+        0xd7 => (2, 8, 0),
+        0xd8 => (2, 16, 0),
+        0xdc | 0xdd => (header_len, 0, n),
+        0xde | 0xdf => (header_len, 0, n * 2),
+        _ => return Err(b)
+    }))
+}
+
+// returns the length of the first complete object in the buffer, or None if it is still incomplete
+fn object_len(buf: &[u8]) -> Result<Option<usize>, u8> {
+    let mut pos = 0;
+    let mut remaining = 1;
+
+    while remaining > 0 {
+        remaining -= 1;
+        match try!(read_header(&buf[pos..])) {
+            Some((header_len, data_len, children)) => {
+                pos += header_len + data_len;
+                if pos > buf.len() {
+                    return Ok(None);
+                }
+                remaining += children;
+            },
+            None => return Ok(None)
+        }
+    }
+
+    Ok(Some(pos))
+}
+
+fn push_len(out: &mut Vec<u8>, n: usize, len_bytes: usize) {
+    for i in (0..len_bytes).rev() {
+        out.push((n >> (i * 8)) as u8);
+    }
+}
+
+fn push_string_header(out: &mut Vec<u8>, is_bin: bool, n: usize) {
+    if is_bin {
+        if n < 0x100 {
+            out.push(0xc4);
+            push_len(out, n, 1);
+        } else if n < 0x10000 {
+            out.push(0xc5);
+            push_len(out, n, 2);
+        } else {
+            out.push(0xc6);
+            push_len(out, n, 4);
+        }
+    } else if n < 0x20 {
+        out.push(0xa0 | (n as u8));
+    } else if n < 0x100 {
+        out.push(0xd9);
+        push_len(out, n, 1);
+    } else if n < 0x10000 {
+        out.push(0xda);
+        push_len(out, n, 2);
+    } else {
+        out.push(0xdb);
+        push_len(out, n, 4);
+    }
+}
+
+// the nvim crate reads str and bin payloads into Strings, so any that aren't valid utf-8,
+// like file names or lines in other encodings, are replaced with a lossy copy.
+// the rest of the message is kept as it is.
+pub fn sanitize_strings(msg: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(msg.len());
+    let mut pos = 0;
+
+    while pos < msg.len() {
+        let (header_len, data_len) = match read_header(&msg[pos..]) {
+            Ok(Some((header_len, data_len, _))) if pos + header_len + data_len <= msg.len() => (header_len, data_len),
+            _ => {
+                out.push_all(&msg[pos..]);
+                break;
+            }
+        };
+        let b = msg[pos];
+        let is_bin = 0xc4 <= b && b <= 0xc6;
+        let is_str = (0xa0 <= b && b <= 0xbf) || (0xd9 <= b && b <= 0xdb);
+        let data = &msg[pos + header_len..pos + header_len + data_len];
+
+        if (is_bin || is_str) && str::from_utf8(data).is_err() {
+            let replaced = String::from_utf8_lossy(data).into_owned();
+            push_string_header(&mut out, is_bin, replaced.len());
+            out.push_all(replaced.as_bytes());
+        } else {
+            out.push_all(&msg[pos..pos + header_len + data_len]);
+        }
+        pos += header_len + data_len;
+    }
+
+    out
+}
+
+// accumulates bytes from a stream and splits them into complete msgpack messages
+pub struct Decoder {
+    buf: Vec<u8>
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder { buf: Vec::new() }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.push_all(bytes);
+    }
+
+    pub fn next_message(&mut self) -> Option<Vec<u8>> {
+        match object_len(self.buf.as_ref()) {
+            Ok(Some(len)) => {
+                let tail = self.buf.split_off(len);
+                Some(mem::replace(&mut self.buf, tail))
+            },
+            Ok(None) => None,
+            Err(b) => {
+                // there's no way to find the next message boundary, so drop everything
+                println!("Error decoding message: invalid byte {:#x}", b);
+                self.buf.clear();
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sanitize_strings, Decoder};
+
+    // [2, "abc", nil]
+    static MESSAGE : [u8; 7] = [0x93, 0x02, 0xa3, 0x61, 0x62, 0x63, 0xc0];
+
+    #[test]
+    fn whole_message() {
+        let mut decoder = Decoder::new();
+        decoder.feed(&MESSAGE);
+        assert_eq!(decoder.next_message(), Some(MESSAGE.to_vec()));
+        assert_eq!(decoder.next_message(), None);
+    }
+
+    #[test]
+    fn message_split_across_reads() {
+        for i in 1..MESSAGE.len() {
+            let mut decoder = Decoder::new();
+            decoder.feed(&MESSAGE[..i]);
+            assert_eq!(decoder.next_message(), None);
+            decoder.feed(&MESSAGE[i..]);
+            assert_eq!(decoder.next_message(), Some(MESSAGE.to_vec()));
+        }
+    }
+
+    #[test]
+    fn several_messages_in_one_read() {
+        let mut decoder = Decoder::new();
+        decoder.feed(&MESSAGE);
+        decoder.feed(&MESSAGE[..3]);
+        assert_eq!(decoder.next_message(), Some(MESSAGE.to_vec()));
+        assert_eq!(decoder.next_message(), None);
+        decoder.feed(&MESSAGE[3..]);
+        assert_eq!(decoder.next_message(), Some(MESSAGE.to_vec()));
+    }
+
+    #[test]
+    fn partial_str8_header() {
+        // ["hi"] with a str 8 header, cut off before the length byte
+        let mut decoder = Decoder::new();
+        decoder.feed(&[0x91, 0xd9]);
+        assert_eq!(decoder.next_message(), None);
+        decoder.feed(&[0x02, 0x68, 0x69]);
+        assert_eq!(decoder.next_message(), Some(vec![0x91, 0xd9, 0x02, 0x68, 0x69]));
+    }
+
+    #[test]
+    fn partial_array16_header() {
+        // [1] with an array 16 header, cut off in the middle of the length
+        let mut decoder = Decoder::new();
+        decoder.feed(&[0xdc, 0x00]);
+        assert_eq!(decoder.next_message(), None);
+        decoder.feed(&[0x01, 0x01]);
+        assert_eq!(decoder.next_message(), Some(vec![0xdc, 0x00, 0x01, 0x01]));
+    }
+
+    #[test]
+    fn partial_ext_header() {
+        // fixext 1 and ext 8 both need their type byte before they're complete
+        let mut decoder = Decoder::new();
+        decoder.feed(&[0xd4, 0x01]);
+        assert_eq!(decoder.next_message(), None);
+        decoder.feed(&[0x05, 0xc7, 0x01, 0x01]);
+        assert_eq!(decoder.next_message(), Some(vec![0xd4, 0x01, 0x05]));
+        assert_eq!(decoder.next_message(), None);
+        decoder.feed(&[0x05]);
+        assert_eq!(decoder.next_message(), Some(vec![0xc7, 0x01, 0x01, 0x05]));
+    }
+
+    #[test]
+    fn invalid_byte_drops_buffer() {
+        let mut decoder = Decoder::new();
+        decoder.feed(&[0xc1, 0x01]);
+        assert_eq!(decoder.next_message(), None);
+        decoder.feed(&MESSAGE);
+        assert_eq!(decoder.next_message(), Some(MESSAGE.to_vec()));
+    }
+
+    #[test]
+    fn valid_messages_are_unchanged() {
+        assert_eq!(sanitize_strings(&MESSAGE), MESSAGE.to_vec());
+        // [b"hi"] as bin 8
+        assert_eq!(sanitize_strings(&[0x91, 0xc4, 0x02, 0x68, 0x69]), vec![0x91, 0xc4, 0x02, 0x68, 0x69]);
+    }
+
+    #[test]
+    fn invalid_bin_payload_is_replaced() {
+        // [2, b"\xffa"] keeps its other items, with the bad byte replaced by U+FFFD
+        let mut decoder = Decoder::new();
+        decoder.feed(&[0x93, 0x02, 0xc4, 0x02, 0xff, 0x61, 0xc0]);
+        let msg = decoder.next_message().unwrap();
+        assert_eq!(sanitize_strings(msg.as_ref()),
+                   vec![0x93, 0x02, 0xc4, 0x04, 0xef, 0xbf, 0xbd, 0x61, 0xc0]);
+    }
+
+    #[test]
+    fn invalid_str_payload_gets_a_longer_header() {
+        // a fixstr that grows past 31 bytes becomes a str 8
+        let mut msg = vec![0x91, 0xbf];
+        for _ in 0..31 {
+            msg.push(0xe9);
+        }
+        let sanitized = sanitize_strings(msg.as_ref());
+        assert_eq!(&sanitized[..3], &[0x91, 0xd9, 93][..]);
+        assert_eq!(sanitized.len(), 3 + 93);
+        assert_eq!(&sanitized[3..6], &[0xef, 0xbf, 0xbd][..]);
+    }
+}
@@ -0,0 +1,380 @@
+use ffi::{RpcClient, RpcError, RpcResult};
+use std::collections::HashSet;
+
+static TIMEOUT_MS : i32 = 1000;
+static FIRST_SIGN_ID : usize = 7000;
+static SPECIAL_CHARS : &'static str = " \t\n*?[{`$\\%#'\"|!<";
+
+pub struct Buffer {
+    pub number: i64,
+    pub name: String,
+    pub is_modified: bool
+}
+
+pub struct QuickfixItem {
+    pub file_name: String,
+    pub line: u64,
+    pub column: u64,
+    pub is_error: bool,
+    pub text: String
+}
+
+pub struct Client {
+    rpc: RpcClient,
+    sign_count: usize,
+    signs_defined: bool,
+    signed_files: HashSet<String>
+}
+
+// escapes a path the same way vim's fnameescape() does so it can be used in an ex command
+pub fn escape_path(path: &str) -> String {
+    let mut escaped = String::new();
+    for (i, c) in path.chars().enumerate() {
+        if SPECIAL_CHARS.contains_char(c) || (i == 0 && (c == '+' || c == '>')) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    if escaped == "-" {
+        escaped = "\\-".to_string();
+    }
+    escaped
+}
+
+// quotes a string as a vimscript literal, which can't span lines
+fn quote_string(s: &str) -> String {
+    format!("'{}'", s.replace("'", "''").replace("\n", " "))
+}
+
+fn get_integer(obj: Option<::neovim::Object>) -> Option<i64> {
+    match obj {
+        Some(::neovim::Object::Integer(n)) => Some(n),
+        Some(::neovim::Object::Boolean(b)) => Some(if b { 1 } else { 0 }),
+        _ => None
+    }
+}
+
+fn get_string(obj: Option<::neovim::Object>) -> Option<String> {
+    match obj {
+        Some(::neovim::Object::String(s)) => Some(s),
+        _ => None
+    }
+}
+
+// nvim's buffer handles are the same as their buffer numbers
+fn get_handle(obj: ::neovim::Object) -> Option<i64> {
+    match obj {
+        ::neovim::Object::Buffer(handle) => Some(handle as i64),
+        ::neovim::Object::Integer(n) => Some(n),
+        _ => None
+    }
+}
+
+impl Client {
+    pub fn new(read_fd: i32, write_fd: i32) -> Client {
+        Client {
+            rpc: RpcClient::new(read_fd, write_fd),
+            sign_count: 0,
+            signs_defined: false,
+            signed_files: HashSet::new()
+        }
+    }
+
+    pub fn recv(&mut self) -> Option<::neovim::Array> {
+        self.rpc.recv()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.rpc.is_connected()
+    }
+
+    pub fn wake_fd(&self) -> i32 {
+        self.rpc.wake_fd()
+    }
+
+    pub fn close(&mut self) {
+        self.rpc.close();
+    }
+
+    pub fn command(&mut self, command: &str) {
+        let mut args = ::neovim::Array::new();
+        args.add_string(command);
+        self.rpc.request("vim_command", &args);
+    }
+
+    pub fn call_function(&mut self, name: &str, fn_args: ::neovim::Array) -> RpcResult {
+        let mut args = ::neovim::Array::new();
+        args.add_string(name);
+        args.add_array(fn_args);
+        self.rpc.call("vim_call_function", &args, TIMEOUT_MS)
+    }
+
+    pub fn eval(&mut self, expr: &str) -> RpcResult {
+        let mut args = ::neovim::Array::new();
+        args.add_string(expr);
+        self.rpc.call("vim_eval", &args, TIMEOUT_MS)
+    }
+
+    pub fn set_bool_option(&mut self, name: &str, value: bool) {
+        let mut args = ::neovim::Array::new();
+        args.add_string(name);
+        args.add_boolean(value);
+        self.rpc.request("vim_set_option", &args);
+    }
+
+    pub fn set_integer_option(&mut self, name: &str, value: i64) {
+        let mut args = ::neovim::Array::new();
+        args.add_string(name);
+        args.add_integer(value);
+        self.rpc.request("vim_set_option", &args);
+    }
+
+    pub fn set_string_var(&mut self, name: &str, value: &str) {
+        let mut args = ::neovim::Array::new();
+        args.add_string(name);
+        args.add_string(value);
+        self.rpc.request("vim_set_var", &args);
+    }
+
+    pub fn edit(&mut self, path: &str) {
+        self.command(format!("edit {}", escape_path(path)).as_ref());
+    }
+
+    pub fn set_cursor(&mut self, line: u64, column: u64) {
+        self.command(format!("call cursor({}, {})", line, column).as_ref());
+    }
+
+    pub fn write(&mut self) {
+        self.command("write");
+    }
+
+    pub fn undo(&mut self) {
+        self.command("undo");
+    }
+
+    pub fn redo(&mut self) {
+        self.command("redo");
+    }
+
+    pub fn close_buffer(&mut self) {
+        self.command("bdelete");
+    }
+
+    // renames the current buffer's file on disk, via eunuch
+    pub fn move_file(&mut self, path: &str) {
+        self.command(format!("Move {}", escape_path(path)).as_ref());
+    }
+
+    pub fn show_buffer(&mut self, number: i64) {
+        self.command(format!("buffer! {}", number).as_ref());
+    }
+
+    // points a buffer at a file that was moved on disk. the buffer is re-edited so vim doesn't
+    // treat the new name as some other existing file, and then any unsaved lines are put back.
+    pub fn rename_buffer(&mut self, number: i64, path: &str) {
+        let is_modified = self.get_buffer_option(number, "modified").unwrap_or(true);
+        self.show_buffer(number);
+        let file_command = format!("silent keepalt file {}", escape_path(path));
+        let unsaved_lines = if is_modified {
+            match self.get_lines() {
+                Ok(lines) => Some(lines),
+                // without the lines, re-editing would throw the changes away
+                Err(_) => {
+                    self.command(file_command.as_ref());
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+        self.command(format!("{} | silent edit!", file_command).as_ref());
+        if let Some(lines) = unsaved_lines {
+            let quoted: Vec<String> = lines.iter().map(|line| quote_string(line.as_ref())).collect();
+            self.command(format!("silent %delete _ | call setline(1, [{}])", quoted.connect(", ")).as_ref());
+        }
+    }
+
+    pub fn write_buffer(&mut self, number: i64) {
+        self.show_buffer(number);
+        self.command("write");
+    }
+
+    // drops the buffer without saving, for when its file is going away
+    pub fn delete_buffer(&mut self, number: i64) {
+        self.command(format!("silent! bdelete! {}", number).as_ref());
+    }
+
+    // closes the buffer, unless it has unsaved changes
+    pub fn close_buffer_number(&mut self, number: i64) {
+        self.command(format!("silent! bdelete {}", number).as_ref());
+    }
+
+    pub fn is_buffer_modified(&mut self, number: i64) -> Result<bool, RpcError> {
+        self.get_buffer_option(number, "modified")
+    }
+
+    pub fn get_lines(&mut self) -> Result<Vec<String>, RpcError> {
+        let obj = try!(self.eval("getline(1, '$')"));
+        let mut lines = Vec::new();
+        if let ::neovim::Object::Array(arr) = obj {
+            for i in 0..arr.len() {
+                lines.push(get_string(arr.get(i)).unwrap_or(String::new()));
+            }
+        }
+        Ok(lines)
+    }
+
+    // changes lines in the current buffer with a single command, so it's undone in one step
+    pub fn set_lines(&mut self, lines: &[(u64, String)]) {
+        let calls: Vec<String> = lines.iter().map(|&(line, ref text)| {
+            format!("call setline({}, {})", line, quote_string(text.as_ref()))
+        }).collect();
+        if calls.len() > 0 {
+            self.command(calls.connect(" | ").as_ref());
+        }
+    }
+
+    pub fn set_quickfix_list(&mut self, items: &[QuickfixItem]) {
+        let mut entries = Vec::new();
+        for item in items.iter() {
+            entries.push(format!(
+                "{{'filename': {}, 'lnum': {}, 'col': {}, 'type': '{}', 'text': {}}}",
+                quote_string(item.file_name.as_ref()),
+                item.line,
+                item.column,
+                if item.is_error { "E" } else { "W" },
+                quote_string(item.text.as_ref())
+            ));
+        }
+        self.command(format!("call setqflist([{}], 'r')", entries.connect(", ")).as_ref());
+    }
+
+    fn get_place_command(index: usize, item: &QuickfixItem) -> String {
+        format!(
+            "silent! sign place {} line={} name={} file={}",
+            FIRST_SIGN_ID + index,
+            item.line,
+            if item.is_error { "SoakError" } else { "SoakWarning" },
+            escape_path(item.file_name.as_ref())
+        )
+    }
+
+    // marks the quickfix items in the sign column of any loaded buffers, all in one command
+    pub fn set_signs(&mut self, items: &[QuickfixItem]) {
+        let mut commands = Vec::new();
+        if !self.signs_defined {
+            commands.push("sign define SoakError text=>> texthl=ErrorMsg".to_string());
+            commands.push("sign define SoakWarning text=>> texthl=WarningMsg".to_string());
+            self.signs_defined = true;
+        }
+        for i in 0..self.sign_count {
+            commands.push(format!("silent! sign unplace {}", FIRST_SIGN_ID + i));
+        }
+        for (i, item) in items.iter().enumerate() {
+            commands.push(Client::get_place_command(i, item));
+        }
+        self.sign_count = items.len();
+        self.signed_files.clear();
+        if commands.len() > 0 {
+            self.command(commands.connect(" | ").as_ref());
+        }
+    }
+
+    // buffers that weren't loaded when the signs were set get theirs the first time they're entered
+    pub fn add_signs_for_file(&mut self, items: &[QuickfixItem], file_name: &str) {
+        if self.signed_files.contains(file_name) {
+            return;
+        }
+        self.signed_files.insert(file_name.to_string());
+        let commands: Vec<String> = items.iter().enumerate()
+            .filter(|&(_, item)| item.file_name == file_name)
+            .map(|(i, item)| Client::get_place_command(i, item))
+            .collect();
+        if commands.len() > 0 {
+            self.command(commands.connect(" | ").as_ref());
+        }
+    }
+
+    pub fn next_error(&mut self) {
+        self.command("silent! cnext");
+    }
+
+    pub fn previous_error(&mut self) {
+        self.command("silent! cprevious");
+    }
+
+    fn get_buffer_option(&mut self, handle: i64, name: &str) -> Result<bool, RpcError> {
+        let mut args = ::neovim::Array::new();
+        args.add_integer(handle);
+        args.add_string(name);
+        let obj = try!(self.rpc.call("buffer_get_option", &args, TIMEOUT_MS));
+        Ok(get_integer(Some(obj)).unwrap_or(0) != 0)
+    }
+
+    // the name is the buffer's full path, or empty if it has no file
+    fn get_buffer(&mut self, handle: i64) -> Result<Buffer, RpcError> {
+        let mut args = ::neovim::Array::new();
+        args.add_integer(handle);
+        let name = try!(self.rpc.call("buffer_get_name", &args, TIMEOUT_MS));
+        Ok(Buffer {
+            number: handle,
+            name: get_string(Some(name)).unwrap_or(String::new()),
+            is_modified: try!(self.get_buffer_option(handle, "modified"))
+        })
+    }
+
+    pub fn current_buffer(&mut self) -> Result<Buffer, RpcError> {
+        let obj = try!(self.rpc.call("vim_get_current_buffer", &::neovim::Array::new(), TIMEOUT_MS));
+        let handle = try!(get_handle(obj).ok_or(RpcError::Remote("Invalid buffer".to_string())));
+        self.get_buffer(handle)
+    }
+
+    pub fn buffer_list(&mut self) -> Result<Vec<Buffer>, RpcError> {
+        let obj = try!(self.rpc.call("vim_get_buffers", &::neovim::Array::new(), TIMEOUT_MS));
+        let mut buffers = Vec::new();
+        if let ::neovim::Object::Array(arr) = obj {
+            for i in 0..arr.len() {
+                if let Some(handle) = arr.get(i).and_then(get_handle) {
+                    if try!(self.get_buffer_option(handle, "buflisted")) {
+                        buffers.push(try!(self.get_buffer(handle)));
+                    }
+                }
+            }
+        }
+        Ok(buffers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_path;
+
+    #[test]
+    fn plain_paths_are_unchanged() {
+        assert_eq!(escape_path("/home/me/src/main.rs"), "/home/me/src/main.rs");
+        assert_eq!(escape_path("a+b>c"), "a+b>c");
+    }
+
+    #[test]
+    fn special_chars_are_escaped() {
+        assert_eq!(escape_path("/my project/a b.rs"), "/my\\ project/a\\ b.rs");
+        assert_eq!(escape_path("100%#1"), "100\\%\\#1");
+        assert_eq!(escape_path("[a]{b}"), "\\[a]\\{b}");
+        assert_eq!(escape_path("it's \"$x\""), "it\\'s\\ \\\"\\$x\\\"");
+        assert_eq!(escape_path("a|b!c<d"), "a\\|b\\!c\\<d");
+        assert_eq!(escape_path("*?`"), "\\*\\?\\`");
+        assert_eq!(escape_path("C:\\x"), "C:\\\\x");
+    }
+
+    #[test]
+    fn leading_plus_and_greater_than_are_escaped() {
+        assert_eq!(escape_path("+cmd"), "\\+cmd");
+        assert_eq!(escape_path(">out"), "\\>out");
+    }
+
+    #[test]
+    fn lone_dash_is_escaped() {
+        assert_eq!(escape_path("-"), "\\-");
+        assert_eq!(escape_path("-a"), "-a");
+    }
+}
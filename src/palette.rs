@@ -0,0 +1,113 @@
+use gtk::traits::*;
+use gtk::widgets;
+use std::path::Path;
+
+static MAX_RESULTS : usize = 50;
+static MAX_RECENT_FILES : usize = 50;
+
+pub fn add_recent_file(state: &mut ::utils::State, path_str: &str) {
+    if path_str.len() == 0 || Path::new(path_str).is_dir() {
+        return;
+    }
+    state.recent_files.retain(|recent_str| recent_str != path_str);
+    state.recent_files.insert(0, path_str.to_string());
+    state.recent_files.truncate(MAX_RECENT_FILES);
+}
+
+// files are shown and matched by their path starting from the project's name
+fn get_display_path(project_path: &Path, path: &Path) -> Option<String> {
+    project_path.parent()
+        .and_then(|parent_path| path.relative_from(parent_path))
+        .and_then(|relative_path| relative_path.to_str())
+        .map(|relative_str| relative_str.to_string())
+}
+
+// enough to break close calls in favor of recent files without burying better matches
+fn get_recent_bonus(state: &::utils::State, path_str: &str) -> i64 {
+    match state.recent_files.iter().position(|recent_str| recent_str == path_str) {
+        Some(i) => ((MAX_RECENT_FILES - i) / 5) as i64,
+        None => 0
+    }
+}
+
+// with no query the recent files are listed, and otherwise every indexed file is ranked
+pub fn update_results(state: &::utils::State, query: &str) {
+    state.palette_store.clear();
+
+    let query = query.trim();
+    let mut results = Vec::new();
+
+    if query.len() == 0 {
+        for (i, path_str) in state.recent_files.iter().enumerate() {
+            let path = Path::new(path_str);
+            if let Some(project_path) = ::utils::get_root_path(state, path) {
+                if let Some(display_str) = get_display_path(&project_path, path) {
+                    results.push((-(i as i64), display_str, path_str.clone()));
+                }
+            }
+        }
+    } else {
+        for (project_path, files) in state.file_index.iter() {
+            for path in files.iter() {
+                if let Some(path_str) = path.to_str() {
+                    if let Some(display_str) = get_display_path(project_path, path) {
+                        let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+                        if let Some(score) = ::fuzzy::score_path(query, name, display_str.as_ref()) {
+                            results.push((score + get_recent_bonus(state, path_str), display_str, path_str.to_string()));
+                        }
+                    }
+                }
+            }
+        }
+        results.sort_by(|a, b| b.0.cmp(&a.0));
+    }
+
+    for &(_, ref display_str, ref path_str) in results.iter().take(MAX_RESULTS) {
+        let mut iter = widgets::TreeIter::new().unwrap();
+        state.palette_store.append(&mut iter, None);
+        state.palette_store.set_string(&iter, 0, display_str.as_ref());
+        state.palette_store.set_string(&iter, 1, path_str.as_ref());
+    }
+
+    let mut first = widgets::TreeIter::new().unwrap();
+    if state.palette_model.iter_children(&mut first, None) {
+        state.palette_selection.select_iter(&first);
+    }
+}
+
+pub fn show_palette(state: &::utils::State, window: &widgets::Window, entry: &widgets::SearchEntry) {
+    entry.set_text("");
+    update_results(state, "");
+    window.show_all();
+    entry.grab_focus();
+}
+
+pub fn move_selection(state: &::utils::State, tree: &mut widgets::TreeView, is_down: bool) {
+    let mut iter = widgets::TreeIter::new().unwrap();
+    if state.palette_selection.get_selected(state.palette_model, &mut iter) {
+        let has_next = if is_down {
+            state.palette_model.iter_next(&mut iter)
+        } else {
+            state.palette_model.iter_previous(&mut iter)
+        };
+        if has_next {
+            if let Some(path) = state.palette_model.get_path(&iter) {
+                tree.set_cursor(&path, None, false);
+            }
+        }
+    }
+}
+
+pub fn open_selected(
+    state: &mut ::utils::State,
+    client: &mut ::nvim::Client,
+    window: &widgets::Window)
+{
+    let mut iter = widgets::TreeIter::new().unwrap();
+    if state.palette_selection.get_selected(state.palette_model, &mut iter) {
+        if let Some(path_str) = state.palette_model.get_value(&iter, 1).get_string() {
+            window.hide();
+            ::projects::open_file(state, client, &path_str);
+        }
+    }
+}
@@ -1,8 +1,9 @@
 use gtk::traits::*;
 use gtk::{self, widgets};
-use std::fs::PathExt;
+use std::env;
+use std::fs::{self, PathExt};
 use std::num::FromPrimitive;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 fn remove_expansions_for_path(state: &mut ::utils::State, path_str: &String) {
@@ -16,13 +17,28 @@ fn remove_expansions_for_path(state: &mut ::utils::State, path_str: &String) {
     }
 }
 
-fn save_project(state: &mut ::utils::State, tree: &mut widgets::TreeView, path_str: &String) {
+pub fn watch_project(state: &::utils::State, path_str: &String) {
+    let path = Path::new(path_str);
+    let ignore = ::utils::get_project_settings(state, path).ignore.unwrap_or(Vec::new());
+    state.watcher.watch(path, ignore);
+}
+
+fn save_project(
+    state: &mut ::utils::State,
+    tree: &mut widgets::TreeView,
+    client: &mut ::nvim::Client,
+    path_str: &String)
+{
     state.projects.insert(path_str.clone());
     ::utils::write_prefs(state);
+    ::session::update_session_path(state, client);
     ::ui::update_project_tree(state, tree);
+    watch_project(state, path_str);
+    ::git::refresh_status(state, Path::new(path_str));
+    ::index::index_project(state, Path::new(path_str));
 }
 
-pub fn new_project(state: &mut ::utils::State, tree: &mut widgets::TreeView) {
+pub fn new_project(state: &mut ::utils::State, tree: &mut widgets::TreeView, client: &mut ::nvim::Client) {
     let dialog = widgets::FileChooserDialog::new(
         "New Project",
         None,
@@ -39,7 +55,7 @@ pub fn new_project(state: &mut ::utils::State, tree: &mut widgets::TreeView) {
                         match Command::new("cargo").arg("new").arg(name_str).arg("--bin")
                             .current_dir(parent_path).status()
                         {
-                            Ok(_) => save_project(state, tree, &path_str),
+                            Ok(_) => save_project(state, tree, client, &path_str),
                             Err(e) => println!("Error creating {}: {}", name_str, e)
                         }
                     }
@@ -50,7 +66,7 @@ pub fn new_project(state: &mut ::utils::State, tree: &mut widgets::TreeView) {
     dialog.destroy();
 }
 
-pub fn import_project(state: &mut ::utils::State, tree: &mut widgets::TreeView) {
+pub fn import_project(state: &mut ::utils::State, tree: &mut widgets::TreeView, client: &mut ::nvim::Client) {
     let dialog = widgets::FileChooserDialog::new(
         "Import",
         None,
@@ -59,32 +75,374 @@ pub fn import_project(state: &mut ::utils::State, tree: &mut widgets::TreeView)
     );
     if let Some(gtk::ResponseType::Ok) = FromPrimitive::from_i32(dialog.run()) {
         if let Some(path_str) = dialog.get_filename() {
-            save_project(state, tree, &path_str);
+            save_project(state, tree, client, &path_str);
         }
     }
     dialog.destroy();
 }
 
-pub fn rename_file(state: &mut ::utils::State, fd: i32) {
-    if let Some(_) = ::utils::get_selected_path(state) {
-        let dialog = widgets::FileChooserDialog::new(
-            "Rename",
-            None,
-            gtk::FileChooserAction::Save,
-            [("Save", gtk::ResponseType::Ok), ("Cancel", gtk::ResponseType::Cancel)]
-        );
-        if let Some(gtk::ResponseType::Ok) = FromPrimitive::from_i32(dialog.run()) {
-            if let Some(path_str) = dialog.get_filename() {
-                state.selection = Some(path_str.clone());
-                ::utils::write_prefs(&state);
-                ::ffi::send_message(fd, format!("Move {}", path_str).as_ref());
+// moves a file or directory on disk and points any open buffers inside it at the new paths
+fn move_item(state: &mut ::utils::State, client: &mut ::nvim::Client, from: &Path, to: &Path) -> bool {
+    if to.exists() || to.starts_with(from) {
+        println!("Error moving {:?}: can't move it to {:?}", from, to);
+        return false;
+    }
+    if let Err(e) = ::utils::move_path(from, to) {
+        println!("Error moving {:?}: {}", from, e);
+        return false;
+    }
+
+    // rename the buffers, then go back to the buffer that was showing
+    match client.buffer_list() {
+        Ok(buffers) => {
+            let current = client.current_buffer().ok();
+            let mut is_renamed = false;
+            for buffer in buffers.iter() {
+                if Path::new(&buffer.name).starts_with(from) {
+                    let new_str = ::utils::replace_path_prefix(&buffer.name, from, to);
+                    client.rename_buffer(buffer.number, new_str.as_ref());
+                    is_renamed = true;
+                }
+            }
+            if let Some(buffer) = current {
+                if is_renamed {
+                    client.show_buffer(buffer.number);
+                }
+            }
+        },
+        Err(e) => println!("Error renaming buffers: {}", e)
+    }
+
+    ::utils::move_expansions(state, from, to);
+    true
+}
+
+// files are moved by eunuch so their buffer follows along, and directories are moved here
+pub fn rename_file(state: &mut ::utils::State, client: &mut ::nvim::Client) {
+    if let Some(path_str) = ::utils::get_selected_path(state) {
+        if !::ui::can_rename(state, &path_str) {
+            return;
+        }
+        let path = PathBuf::from(&path_str);
+        if let Some(parent_path) = path.parent() {
+            let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+            if let Some(new_path_str) = choose_path("Rename", gtk::FileChooserAction::Save, parent_path, name) {
+                if path.is_dir() {
+                    move_item(state, client, &path, Path::new(&new_path_str));
+                } else {
+                    state.selection = Some(new_path_str.clone());
+                    ::utils::write_prefs(&state);
+                    client.move_file(new_path_str.as_ref());
+                }
             }
         }
-        dialog.destroy();
     }
 }
 
-pub fn remove_item(state: &mut ::utils::State, tree: &mut widgets::TreeView, fd: i32) {
+// new items go inside the selected directory, or next to the selected file
+fn get_selected_dir(state: &::utils::State) -> Option<PathBuf> {
+    if let Some(path_str) = ::utils::get_selected_path(state) {
+        let path = Path::new(&path_str);
+        if path.is_dir() {
+            Some(path.to_path_buf())
+        } else {
+            path.parent().map(|parent| parent.to_path_buf())
+        }
+    } else {
+        None
+    }
+}
+
+fn choose_path(title: &str, action: gtk::FileChooserAction, dir: &Path, name: &str) -> Option<String> {
+    let dialog = widgets::FileChooserDialog::new(
+        title,
+        None,
+        action,
+        [("Save", gtk::ResponseType::Ok), ("Cancel", gtk::ResponseType::Cancel)]
+    );
+    if let Some(dir_str) = dir.to_str() {
+        dialog.set_current_folder(dir_str);
+    }
+    if name.len() > 0 {
+        dialog.set_current_name(name);
+    }
+    let path_str = match FromPrimitive::from_i32(dialog.run()) {
+        Some(gtk::ResponseType::Ok) => dialog.get_filename(),
+        _ => None
+    };
+    dialog.destroy();
+    path_str
+}
+
+pub fn new_file(state: &mut ::utils::State, client: &mut ::nvim::Client) {
+    if let Some(dir) = get_selected_dir(state) {
+        if let Some(path_str) = choose_path("New File", gtk::FileChooserAction::Save, &dir, "") {
+            let path = Path::new(&path_str);
+            if !path.exists() {
+                if let Err(e) = fs::File::create(path) {
+                    println!("Error creating {}: {}", path_str, e);
+                    return;
+                }
+            }
+            state.selection = Some(path_str.clone());
+            ::utils::write_prefs(state);
+            client.edit(path_str.as_ref());
+        }
+    }
+}
+
+pub fn new_folder(state: &mut ::utils::State) {
+    if let Some(dir) = get_selected_dir(state) {
+        if let Some(path_str) = choose_path("New Folder", gtk::FileChooserAction::CreateFolder, &dir, "") {
+            let path = Path::new(&path_str);
+            if !path.exists() {
+                if let Err(e) = fs::create_dir_all(path) {
+                    println!("Error creating {}: {}", path_str, e);
+                    return;
+                }
+            }
+            // make sure the new folder is visible once the watcher adds it
+            if let Some(parent_str) = path.parent().and_then(|p| p.to_str()) {
+                state.expansions.insert(parent_str.to_string());
+                ::utils::write_prefs(state);
+            }
+        }
+    }
+}
+
+// suggests "name_copy.ext" next to the original
+fn get_copy_name(path: &Path) -> String {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    match path.extension().and_then(|s| s.to_str()) {
+        Some(ext) if !path.is_dir() => format!("{}_copy.{}", stem, ext),
+        _ => format!("{}_copy", stem)
+    }
+}
+
+pub fn duplicate_item(state: &mut ::utils::State, client: &mut ::nvim::Client) {
+    if let Some(path_str) = ::utils::get_selected_path(state) {
+        if state.projects.contains(&path_str) {
+            return;
+        }
+        let path = Path::new(&path_str);
+        if let Some(parent_path) = path.parent() {
+            let copy_name = get_copy_name(path);
+            if let Some(copy_str) = choose_path("Duplicate", gtk::FileChooserAction::Save, parent_path, copy_name.as_ref()) {
+                let copy_path_buf = PathBuf::from(&copy_str);
+                if copy_path_buf.exists() {
+                    println!("Error duplicating {}: {} already exists", path_str, copy_str);
+                    return;
+                }
+                if let Err(e) = ::utils::copy_path(path, &copy_path_buf) {
+                    println!("Error duplicating {}: {}", path_str, e);
+                    return;
+                }
+                if !copy_path_buf.is_dir() {
+                    state.selection = Some(copy_str.clone());
+                    ::utils::write_prefs(state);
+                    client.edit(copy_str.as_ref());
+                }
+            }
+        }
+    }
+}
+
+// relative paths start from the closest Cargo.toml, which is what mod paths are based on
+pub fn copy_selected_path(state: &::utils::State, is_relative: bool) {
+    if let Some(path_str) = ::utils::get_selected_path(state) {
+        let path = Path::new(&path_str);
+        if is_relative {
+            if let Some(project_path) = ::utils::get_project_path(state, path) {
+                if let Some(relative_str) = path.relative_from(&project_path).and_then(|p| p.to_str()) {
+                    ::ui::copy_to_clipboard(relative_str);
+                }
+            }
+        } else {
+            ::ui::copy_to_clipboard(path_str.as_ref());
+        }
+    }
+}
+
+pub fn open_terminal(state: &::utils::State) {
+    if let Some(dir) = get_selected_dir(state) {
+        let terminal = env::var("TERMINAL").unwrap_or("x-terminal-emulator".to_string());
+        if let Err(e) = Command::new(&terminal).current_dir(&dir).spawn() {
+            println!("Error opening {}: {}", terminal, e);
+        }
+    }
+}
+
+pub fn get_selected_uris(state: &::utils::State) -> Vec<String> {
+    match ::utils::get_selected_path(state) {
+        Some(path_str) => vec![::utils::path_to_uri(path_str.as_ref())],
+        None => Vec::new()
+    }
+}
+
+// things dropped onto a folder go inside it, and things dropped onto a file go next to it.
+// anything already in a project is moved, and anything from elsewhere is copied in.
+pub fn drop_uris(
+    state: &mut ::utils::State,
+    client: &mut ::nvim::Client,
+    target_str: Option<String>,
+    uris: Vec<String>) -> bool
+{
+    let target_path = match target_str {
+        Some(ref target_str) if target_str.len() > 0 => PathBuf::from(target_str),
+        _ => return false
+    };
+    let target_dir = if target_path.is_dir() {
+        target_path
+    } else {
+        match target_path.parent() {
+            Some(parent_path) => parent_path.to_path_buf(),
+            None => return false
+        }
+    };
+
+    let mut is_dropped = false;
+    for uri in uris.iter() {
+        if let Some(path) = ::utils::uri_to_path(uri.as_ref()) {
+            let new_path = match path.file_name() {
+                Some(name) => target_dir.join(name),
+                None => continue
+            };
+            if new_path == path || ::utils::is_project_root(state, &path) {
+                continue;
+            }
+
+            let is_in_project = state.projects.iter().any(|project_str| path.starts_with(project_str));
+            if is_in_project {
+                is_dropped = move_item(state, client, &path, &new_path) || is_dropped;
+            } else if new_path.exists() {
+                println!("Error copying {:?}: {:?} already exists", path, new_path);
+            } else {
+                match ::utils::copy_path(&path, &new_path) {
+                    Ok(_) => is_dropped = true,
+                    Err(e) => println!("Error copying {:?}: {}", path, e)
+                }
+            }
+        }
+    }
+
+    // make sure the dropped items can be seen once the watcher adds them
+    if is_dropped {
+        if let Some(target_dir_str) = target_dir.to_str() {
+            state.expansions.insert(target_dir_str.to_string());
+            ::utils::write_prefs(state);
+        }
+    }
+    is_dropped
+}
+
+fn trash_item(state: &mut ::utils::State, client: &mut ::nvim::Client, path_str: &String) {
+    let path = Path::new(path_str);
+
+    // the buffers inside it are only closed once it's in the trash, so they're kept if that fails
+    let mut buffers = Vec::new();
+    match client.buffer_list() {
+        Ok(buffer_list) => {
+            for buffer in buffer_list.iter() {
+                if buffer.name.len() > 0 && Path::new(&buffer.name).starts_with(path) {
+                    buffers.push((buffer.number, buffer.name.clone()));
+                }
+            }
+        },
+        Err(e) => println!("Error listing buffers: {}", e)
+    }
+
+    let buffer_names = buffers.iter().map(|&(_, ref name)| name.clone()).collect();
+    match ::trash::trash_path(path, buffer_names) {
+        Ok(item) => {
+            for &(number, _) in buffers.iter() {
+                client.delete_buffer(number);
+            }
+            state.trashed.push(item);
+        },
+        Err(e) => println!("Error moving {} to the trash: {}", path_str, e)
+    }
+}
+
+// restores whatever was removed most recently and reopens its buffers
+pub fn undo_delete(state: &mut ::utils::State, client: &mut ::nvim::Client) {
+    if let Some(item) = state.trashed.pop() {
+        if let Err(e) = ::trash::restore(&item) {
+            println!("Error restoring {:?}, but it's still in the trash: {}", item.original_path, e);
+            return;
+        }
+        for buffer_str in item.buffers.iter() {
+            client.edit(buffer_str.as_ref());
+        }
+        if let Some(path_str) = item.original_path.to_str() {
+            state.selection = Some(path_str.to_string());
+            ::utils::write_prefs(state);
+            if !item.original_path.is_dir() && item.buffers.is_empty() {
+                client.edit(path_str);
+            }
+        }
+    }
+}
+
+// asks what to do with unsaved buffers inside the path, or anywhere if there's no path,
+// and returns false if the user cancels or something couldn't be saved
+pub fn confirm_unsaved_buffers(state: &::utils::State, client: &mut ::nvim::Client, path: Option<&Path>) -> bool {
+    let buffers = match client.buffer_list() {
+        Ok(buffers) => buffers,
+        Err(e) => {
+            println!("Error listing buffers: {}", e);
+            return true;
+        }
+    };
+    // buffers without a file have nowhere to be saved, so they're left out
+    let modified: Vec<&::nvim::Buffer> = buffers.iter().filter(|buffer| {
+        buffer.is_modified && buffer.name.len() > 0 &&
+            path.map(|path| Path::new(&buffer.name).starts_with(path)).unwrap_or(true)
+    }).collect();
+    if modified.len() == 0 {
+        return true;
+    }
+
+    let names: Vec<String> = modified.iter().map(|buffer| ::utils::get_display_path(state, Path::new(&buffer.name))).collect();
+    match ::ui::ask_to_save(state, names.as_ref()) {
+        ::ui::SaveChoice::Save => {
+            let current = client.current_buffer().ok();
+            for buffer in modified.iter() {
+                client.write_buffer(buffer.number);
+            }
+            if let Some(buffer) = current {
+                client.show_buffer(buffer.number);
+            }
+            // buffers that couldn't be written are still modified
+            match client.buffer_list() {
+                Ok(buffers) => !buffers.iter().any(|buffer| {
+                    buffer.is_modified && modified.iter().any(|m| m.number == buffer.number)
+                }),
+                Err(_) => false
+            }
+        },
+        ::ui::SaveChoice::Discard => true,
+        ::ui::SaveChoice::Cancel => false
+    }
+}
+
+fn close_project_buffers(client: &mut ::nvim::Client, project_path: &Path) {
+    match client.buffer_list() {
+        Ok(buffers) => {
+            for buffer in buffers.iter() {
+                if buffer.name.len() > 0 && Path::new(&buffer.name).starts_with(project_path) {
+                    client.delete_buffer(buffer.number);
+                }
+            }
+        },
+        Err(e) => println!("Error listing buffers: {}", e)
+    }
+}
+
+pub fn remove_item(
+    state: &mut ::utils::State,
+    tree: &mut widgets::TreeView,
+    client: &mut ::nvim::Client)
+{
     if let Some(path_str) = ::utils::get_selected_path(state) {
         if let Some(dialog) = widgets::MessageDialog::new_with_markup(
             Some(state.window.clone()),
@@ -93,38 +451,62 @@ pub fn remove_item(state: &mut ::utils::State, tree: &mut widgets::TreeView, fd:
             gtk::ButtonsType::OkCancel,
             if state.projects.contains(&path_str) {
                 "Remove this project? It WILL NOT be deleted from the disk."
+            } else if Path::new(&path_str).is_dir() {
+                "Remove this folder? It will be moved to the trash."
             } else {
-                "Remove this file? It WILL be deleted from the disk."
+                "Remove this file? It will be moved to the trash."
             }
         ) {
-            if let Some(gtk::ResponseType::Ok) = FromPrimitive::from_i32(dialog.run()) {
+            let is_ok = match FromPrimitive::from_i32(dialog.run()) {
+                Some(gtk::ResponseType::Ok) => true,
+                _ => false
+            };
+            dialog.destroy();
+            if is_ok {
                 if state.projects.contains(&path_str) {
+                    if !confirm_unsaved_buffers(state, client, Some(Path::new(&path_str))) {
+                        return;
+                    }
+                    close_project_buffers(client, Path::new(&path_str));
                     state.projects.remove(&path_str);
+                    state.watcher.unwatch(Path::new(&path_str));
+                    state.file_index.remove(&PathBuf::from(&path_str));
                     remove_expansions_for_path(state, &path_str);
                     ::utils::write_prefs(state);
+                    ::session::update_session_path(state, client);
                     ::ui::update_project_tree(state, tree);
-                    ::ffi::send_message(fd, "bd");
                 } else {
-                    ::ffi::send_message(fd, "call delete(expand('%')) | bdelete!");
+                    trash_item(state, client, &path_str);
                 }
             }
-            dialog.destroy();
         }
     }
 }
 
-pub fn set_selection(state: &mut ::utils::State, tree: &mut widgets::TreeView, fd: i32) {
+pub fn open_file(state: &mut ::utils::State, client: &mut ::nvim::Client, path_str: &String) {
+    state.selection = Some(path_str.clone());
+    ::utils::write_prefs(state);
+    ::ui::update_project_buttons(state);
+    client.edit(path_str.as_ref());
+}
+
+pub fn set_selection(state: &mut ::utils::State, client: &mut ::nvim::Client) {
     if !state.is_refreshing_tree {
         if let Some(path_str) = ::utils::get_selected_path(state) {
-            state.selection = Some(path_str.clone());
-            ::utils::write_prefs(state);
-            ::ui::update_project_tree(state, tree);
-            ::ffi::send_message(fd, format!("e {}", path_str).as_ref());
+            open_file(state, client, &path_str);
         }
     }
 }
 
+// rows expanded and collapsed by refreshing or filtering the tree aren't remembered
+fn is_tracking_expansions(state: &::utils::State) -> bool {
+    !state.is_refreshing_tree && state.filter_query.len() == 0
+}
+
 pub fn remove_expansion(state: &mut ::utils::State, iter: &widgets::TreeIter) {
+    if !is_tracking_expansions(state) {
+        return;
+    }
     if let Some(path_str) = state.tree_model.get_value(iter, 1).get_string() {
         remove_expansions_for_path(state, &path_str);
         ::utils::write_prefs(state);
@@ -132,8 +514,33 @@ pub fn remove_expansion(state: &mut ::utils::State, iter: &widgets::TreeIter) {
 }
 
 pub fn add_expansion(state: &mut ::utils::State, iter: &widgets::TreeIter) {
+    if !is_tracking_expansions(state) {
+        return;
+    }
     if let Some(path_str) = state.tree_model.get_value(iter, 1).get_string() {
         state.expansions.insert(path_str);
         ::utils::write_prefs(state);
     }
 }
+
+// sets buffer-local options from the settings of the project the buffer belongs to
+pub fn apply_project_settings(state: &::utils::State, client: &mut ::nvim::Client, path_str: &str) {
+    if let Some(project_path) = ::utils::get_project_path(state, Path::new(path_str)) {
+        let settings = ::utils::get_project_settings(state, &project_path);
+
+        if let Some(editor) = settings.editor {
+            if let Some(tab_width) = editor.tab_width {
+                client.command(format!("setlocal tabstop={} shiftwidth={}", tab_width, tab_width).as_ref());
+            }
+            if let Some(expand_tab) = editor.expand_tab {
+                client.command(if expand_tab { "setlocal expandtab" } else { "setlocal noexpandtab" });
+            }
+            if let Some(text_width) = editor.text_width {
+                client.command(format!("setlocal textwidth={}", text_width).as_ref());
+            }
+        }
+
+        let rustfmt_on_save = settings.rustfmt_on_save.unwrap_or(false);
+        client.command(format!("let b:soak_rustfmt = {}", if rustfmt_on_save { 1 } else { 0 }).as_ref());
+    }
+}
@@ -0,0 +1,661 @@
+use glib;
+use gtk::traits::*;
+use gtk::widgets;
+use libc::{c_char, c_int, c_uint, c_ulong, c_void};
+use std::collections::BTreeSet;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io::Read;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+static MAX_MATCHES : usize = 5000;
+static MAX_FILE_SIZE : u64 = 4 * 1024 * 1024;
+static MAX_CONTEXT_CHARS : usize = 40;
+static MAX_LINE_CHARS : usize = 160;
+
+static G_REGEX_CASELESS : c_uint = 1;
+static G_REGEX_OPTIMIZE : c_uint = 1 << 13;
+
+#[repr(C)]
+struct GError {
+    domain: u32,
+    code: c_int,
+    message: *mut c_char
+}
+
+#[link(name = "glib-2.0")]
+extern "C" {
+    fn g_free (mem: *mut c_void);
+    fn g_error_free (error: *mut GError);
+    fn g_regex_new (
+        pattern: *const c_char,
+        compile_options: c_uint,
+        match_options: c_uint,
+        error: *mut *mut GError
+    ) -> *mut c_void;
+    fn g_regex_ref (regex: *mut c_void) -> *mut c_void;
+    fn g_regex_unref (regex: *mut c_void);
+    fn g_regex_escape_string (string: *const c_char, length: c_int) -> *mut c_char;
+    fn g_regex_match_full (
+        regex: *mut c_void,
+        string: *const c_char,
+        string_len: isize,
+        start_position: c_int,
+        match_options: c_uint,
+        match_info: *mut *mut c_void,
+        error: *mut *mut GError
+    ) -> c_int;
+    fn g_match_info_matches (match_info: *mut c_void) -> c_int;
+    fn g_match_info_fetch_pos (match_info: *mut c_void, match_num: c_int, start_pos: *mut c_int, end_pos: *mut c_int) -> c_int;
+    fn g_match_info_next (match_info: *mut c_void, error: *mut *mut GError) -> c_int;
+    fn g_match_info_expand_references (
+        match_info: *mut c_void,
+        string_to_expand: *const c_char,
+        error: *mut *mut GError
+    ) -> *mut c_char;
+    fn g_match_info_free (match_info: *mut c_void);
+}
+
+#[link(name = "gobject-2.0")]
+extern "C" {
+    fn g_signal_connect_data (
+        instance: *mut c_void,
+        detailed_signal: *const c_char,
+        handler: *const c_void,
+        data: *mut c_void,
+        destroy_data: extern "C" fn(*mut c_void, *mut c_void),
+        connect_flags: c_uint
+    ) -> c_ulong;
+}
+
+pub struct SearchOptions {
+    pub query: String,
+    pub is_regex: bool,
+    pub is_case_sensitive: bool,
+    pub is_whole_word: bool,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>
+}
+
+pub struct Match {
+    pub line: u64,
+    // byte offsets into the line
+    pub start: usize,
+    pub end: usize,
+    pub text: String
+}
+
+pub struct FileMatches {
+    pub path: PathBuf,
+    pub matches: Vec<Match>
+}
+
+pub enum SearchEvent {
+    Found(u64, FileMatches),
+    Finished(u64, bool)
+}
+
+// the search that's running or was run last, whose results are in the panel
+pub struct Search {
+    id: u64,
+    regex: Regex,
+    is_regex: bool,
+    is_cancelled: Arc<AtomicBool>,
+    is_finished: bool,
+    is_truncated: bool,
+    results: Vec<FileMatches>
+}
+
+// include and exclude patterns are separated by commas
+pub fn split_patterns(text: &str) -> Vec<String> {
+    text.split(',').map(|s| s.trim()).filter(|s| s.len() > 0).map(|s| s.to_string()).collect()
+}
+
+fn get_regex(options: &SearchOptions) -> Result<Regex, String> {
+    let pattern = if options.is_regex {
+        options.query.clone()
+    } else {
+        Regex::escape(options.query.as_ref())
+    };
+    let pattern = if options.is_whole_word { format!("\\b(?:{})\\b", pattern) } else { pattern };
+    Regex::new(pattern.as_ref(), options.is_case_sensitive)
+}
+
+// files that are too big or aren't valid utf-8 are skipped
+fn search_file(regex: &Regex, path: &Path) -> Option<FileMatches> {
+    match fs::metadata(path) {
+        Ok(metadata) => if metadata.len() > MAX_FILE_SIZE { return None; },
+        Err(_) => return None
+    }
+    let mut contents = String::new();
+    match fs::File::open(path) {
+        Ok(mut f) => if f.read_to_string(&mut contents).is_err() || contents.contains('\0') { return None; },
+        Err(_) => return None
+    }
+
+    let mut matches = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        for (start, end) in regex.find_all(line).into_iter() {
+            matches.push(Match { line: (i + 1) as u64, start: start, end: end, text: line.to_string() });
+        }
+    }
+    if matches.len() > 0 {
+        Some(FileMatches { path: path.to_path_buf(), matches: matches })
+    } else {
+        None
+    }
+}
+
+// searches every project in the background, sending the matches in each file as they're found
+pub fn start_search(state: &mut ::utils::State, options: SearchOptions) {
+    if let Some(ref search) = state.search {
+        search.is_cancelled.store(true, Ordering::SeqCst);
+    }
+    state.search_store.clear();
+
+    if options.query.len() == 0 {
+        state.search = None;
+        state.search_label.set_text("");
+        return;
+    }
+    let regex = match get_regex(&options) {
+        Ok(regex) => regex,
+        Err(e) => {
+            state.search = None;
+            state.search_label.set_text(format!("Invalid search: {}", e).as_ref());
+            return;
+        }
+    };
+
+    let id = state.search.as_ref().map(|search| search.id + 1).unwrap_or(0);
+    let is_cancelled = Arc::new(AtomicBool::new(false));
+    let mut projects = Vec::new();
+    for path_str in state.projects.iter() {
+        let project_path = PathBuf::from(path_str);
+        let matcher = ::utils::get_matcher(state, &project_path);
+        projects.push((project_path, matcher));
+    }
+
+    let thread_regex = regex.clone();
+    let thread_is_cancelled = is_cancelled.clone();
+    let notifier = state.search_notifier.clone();
+    thread::spawn(move || {
+        let mut count = 0;
+        for (project_path, mut matcher) in projects.into_iter() {
+            let mut files = BTreeSet::new();
+            ::index::add_files(&mut matcher, &project_path, &mut files);
+            for path in files.iter() {
+                if thread_is_cancelled.load(Ordering::SeqCst) {
+                    return;
+                }
+                if options.include.len() > 0 && !::ignore::is_any_match(options.include.as_ref(), &project_path, path) {
+                    continue;
+                }
+                if ::ignore::is_any_match(options.exclude.as_ref(), &project_path, path) {
+                    continue;
+                }
+                if let Some(file_matches) = search_file(&thread_regex, path) {
+                    count += file_matches.matches.len();
+                    notifier.send(SearchEvent::Found(id, file_matches));
+                    if count >= MAX_MATCHES {
+                        notifier.send(SearchEvent::Finished(id, true));
+                        return;
+                    }
+                }
+            }
+        }
+        notifier.send(SearchEvent::Finished(id, false));
+    });
+
+    state.search = Some(Search {
+        id: id,
+        regex: regex,
+        is_regex: options.is_regex,
+        is_cancelled: is_cancelled,
+        is_finished: false,
+        is_truncated: false,
+        results: Vec::new()
+    });
+    update_label(state);
+}
+
+fn update_label(state: &::utils::State) {
+    if let Some(ref search) = state.search {
+        let count = search.results.iter().fold(0, |sum, file_matches| sum + file_matches.matches.len());
+        let text = if !search.is_finished {
+            format!("Searching... {} matches in {} files", count, search.results.len())
+        } else if search.is_truncated {
+            format!("Stopped after {} matches in {} files", count, search.results.len())
+        } else {
+            format!("{} matches in {} files", count, search.results.len())
+        };
+        state.search_label.set_text(text.as_ref());
+    }
+}
+
+// cuts the line down to the text around the match, keeping to char boundaries
+fn get_context(text: &str, start: usize, end: usize) -> (String, String, String) {
+    let before = text[..start].trim_left();
+    let before_chars = before.chars().count();
+    let before: String = before.chars().skip(before_chars.saturating_sub(MAX_CONTEXT_CHARS)).collect();
+    let after: String = text[end..].chars().take(MAX_LINE_CHARS.saturating_sub(MAX_CONTEXT_CHARS)).collect();
+    (before, text[start..end].to_string(), after)
+}
+
+fn get_replacement(search: &Search, m: &Match, replacement: &str) -> Result<String, String> {
+    if search.is_regex {
+        search.regex.expand(m.text.as_ref(), m.start, replacement)
+    } else {
+        Ok(replacement.to_string())
+    }
+}
+
+// shows the match in bold, or struck out next to its replacement while previewing
+fn get_match_markup(search: &Search, m: &Match, replacement: Option<&str>) -> String {
+    let (before, matched, after) = get_context(m.text.as_ref(), m.start, m.end);
+    let highlight = match replacement.map(|replacement| get_replacement(search, m, replacement)) {
+        Some(Ok(new_text)) => format!("<s>{}</s><b>{}</b>", ::utils::escape_markup(matched.as_ref()), ::utils::escape_markup(new_text.as_ref())),
+        _ => format!("<b>{}</b>", ::utils::escape_markup(matched.as_ref()))
+    };
+    format!("{}:{}: {}{}{}", m.line, m.start + 1, ::utils::escape_markup(before.as_ref()), highlight, ::utils::escape_markup(after.as_ref()))
+}
+
+fn set_checked(state: &::utils::State, iter: &widgets::TreeIter, is_checked: bool) {
+    let mut value = glib::Value::new();
+    value.init(glib::Type::Bool);
+    value.set_boolean(is_checked);
+    state.search_store.set_value(iter, 4, &value);
+}
+
+fn add_rows(state: &::utils::State, file_matches: &FileMatches, replacement: Option<&str>) {
+    let search = match state.search {
+        Some(ref search) => search,
+        None => return
+    };
+    let path_str = file_matches.path.to_str().unwrap_or("");
+    let mut iter = widgets::TreeIter::new().unwrap();
+    state.search_store.append(&mut iter, None);
+    let text = format!("<b>{}</b> ({})", ::utils::escape_markup(::utils::get_display_path(state, &file_matches.path).as_ref()),
+                       file_matches.matches.len());
+    state.search_store.set_string(&iter, 0, text.as_ref());
+    state.search_store.set_string(&iter, 1, path_str);
+    state.search_store.set_string(&iter, 2, "1");
+    state.search_store.set_string(&iter, 3, "1");
+    set_checked(state, &iter, true);
+
+    for m in file_matches.matches.iter() {
+        let mut child = widgets::TreeIter::new().unwrap();
+        state.search_store.append(&mut child, Some(&iter));
+        state.search_store.set_string(&child, 0, get_match_markup(search, m, replacement).as_ref());
+        state.search_store.set_string(&child, 1, path_str);
+        state.search_store.set_string(&child, 2, m.line.to_string().as_ref());
+        state.search_store.set_string(&child, 3, (m.start + 1).to_string().as_ref());
+        set_checked(state, &child, true);
+    }
+}
+
+pub fn handle_event(state: &mut ::utils::State, event: SearchEvent, replacement: Option<&str>) {
+    let id = match event {
+        SearchEvent::Found(id, _) | SearchEvent::Finished(id, _) => id
+    };
+    // ignore anything left over from an earlier search
+    if state.search.as_ref().map(|search| search.id != id).unwrap_or(true) {
+        return;
+    }
+    match event {
+        SearchEvent::Found(_, file_matches) => {
+            add_rows(state, &file_matches, replacement);
+            if let Some(ref mut search) = state.search {
+                search.results.push(file_matches);
+            }
+        },
+        SearchEvent::Finished(_, is_truncated) => {
+            if let Some(ref mut search) = state.search {
+                search.is_finished = true;
+                search.is_truncated = is_truncated;
+            }
+        }
+    }
+    update_label(state);
+}
+
+// the rows are in the same order as the results, so they can be walked side by side
+pub fn update_preview(state: &::utils::State, replacement: Option<&str>) {
+    let search = match state.search {
+        Some(ref search) => search,
+        None => return
+    };
+    let mut iter = widgets::TreeIter::new().unwrap();
+    if !state.search_model.iter_children(&mut iter, None) {
+        return;
+    }
+    for file_matches in search.results.iter() {
+        let mut child = widgets::TreeIter::new().unwrap();
+        if state.search_model.iter_children(&mut child, Some(&iter)) {
+            for m in file_matches.matches.iter() {
+                state.search_store.set_string(&child, 0, get_match_markup(search, m, replacement).as_ref());
+                if !state.search_model.iter_next(&mut child) {
+                    break;
+                }
+            }
+        }
+        if !state.search_model.iter_next(&mut iter) {
+            break;
+        }
+    }
+}
+
+fn set_children_checked(state: &::utils::State, iter: &widgets::TreeIter, is_checked: bool) {
+    let mut child = widgets::TreeIter::new().unwrap();
+    if state.search_model.iter_children(&mut child, Some(iter)) {
+        loop {
+            set_checked(state, &child, is_checked);
+            if !state.search_model.iter_next(&mut child) {
+                break;
+            }
+        }
+    }
+}
+
+fn is_any_child_checked(state: &::utils::State, iter: &widgets::TreeIter) -> bool {
+    let mut child = widgets::TreeIter::new().unwrap();
+    if state.search_model.iter_children(&mut child, Some(iter)) {
+        loop {
+            if state.search_model.get_value(&child, 4).get_boolean() {
+                return true;
+            }
+            if !state.search_model.iter_next(&mut child) {
+                break;
+            }
+        }
+    }
+    false
+}
+
+// checking or unchecking a file does the same to all of its matches,
+// and a file stays checked as long as any of its matches are
+pub fn toggle_row(state: &::utils::State, path_str: &str) {
+    let mut iter = widgets::TreeIter::new().unwrap();
+    if !state.search_model.get_iter_from_string(&mut iter, path_str) {
+        return;
+    }
+    let is_checked = !state.search_model.get_value(&iter, 4).get_boolean();
+    set_checked(state, &iter, is_checked);
+
+    let mut parent = widgets::TreeIter::new().unwrap();
+    if state.search_model.iter_parent(&mut parent, &iter) {
+        let is_parent_checked = is_any_child_checked(state, &parent);
+        set_checked(state, &parent, is_parent_checked);
+    } else {
+        set_children_checked(state, &iter, is_checked);
+    }
+}
+
+pub fn open_match(state: &::utils::State, client: &mut ::nvim::Client) {
+    let mut iter = widgets::TreeIter::new().unwrap();
+    if state.search_selection.get_selected(state.search_model, &mut iter) {
+        if let Some(path_str) = state.search_model.get_value(&iter, 1).get_string() {
+            let line = state.search_model.get_value(&iter, 2).get_string()
+                .and_then(|s| s.parse::<u64>().ok()).unwrap_or(1);
+            let column = state.search_model.get_value(&iter, 3).get_string()
+                .and_then(|s| s.parse::<u64>().ok()).unwrap_or(1);
+            client.edit(path_str.as_ref());
+            client.set_cursor(line, column);
+        }
+    }
+}
+
+// replaces the given matches, which are all on the same line
+fn get_new_line(search: &Search, matches: &[&Match], replacement: &str) -> Result<String, String> {
+    let text = matches[0].text.clone();
+    let mut new_line = String::new();
+    let mut offset = 0;
+    for m in matches.iter() {
+        let new_text = try!(get_replacement(search, m, replacement));
+        new_line.push_str(&text[offset..m.start]);
+        new_line.push_str(new_text.as_ref());
+        offset = m.end;
+    }
+    new_line.push_str(&text[offset..]);
+    Ok(new_line)
+}
+
+fn get_checked_matches<'a>(state: &::utils::State, search: &'a Search) -> Vec<(&'a Path, Vec<&'a Match>)> {
+    let mut checked = Vec::new();
+    let mut iter = widgets::TreeIter::new().unwrap();
+    if !state.search_model.iter_children(&mut iter, None) {
+        return checked;
+    }
+    for file_matches in search.results.iter() {
+        let mut matches = Vec::new();
+        let mut child = widgets::TreeIter::new().unwrap();
+        if state.search_model.iter_children(&mut child, Some(&iter)) {
+            for m in file_matches.matches.iter() {
+                if state.search_model.get_value(&child, 4).get_boolean() {
+                    matches.push(m);
+                }
+                if !state.search_model.iter_next(&mut child) {
+                    break;
+                }
+            }
+        }
+        if matches.len() > 0 {
+            checked.push((file_matches.path.as_path(), matches));
+        }
+        if !state.search_model.iter_next(&mut iter) {
+            break;
+        }
+    }
+    checked
+}
+
+// the changes are made in nvim's buffers rather than on disk, so they can be undone and reviewed before saving.
+// lines that changed since the search are left alone.
+pub fn replace_checked(state: &mut ::utils::State, client: &mut ::nvim::Client, replacement: &str) {
+    let (replaced_count, file_count, skipped_count) = {
+        let search = match state.search {
+            Some(ref search) if search.is_finished => search,
+            _ => return
+        };
+        let current = client.current_buffer().ok();
+        let mut replaced_count = 0;
+        let mut file_count = 0;
+        let mut skipped_count = 0;
+
+        for (path, matches) in get_checked_matches(state, search).into_iter() {
+            let path_str = match path.to_str() {
+                Some(path_str) => path_str,
+                None => continue
+            };
+            // the lines are read and set in the current buffer, so make sure the edit really switched to it,
+            // since it fails for things like swap file conflicts
+            client.edit(path_str);
+            let is_current = client.current_buffer().map(|buffer| Path::new(&buffer.name) == path).unwrap_or(false);
+            if !is_current {
+                println!("Error opening {} to replace in it", path_str);
+                skipped_count += matches.len();
+                continue;
+            }
+            let lines = match client.get_lines() {
+                Ok(lines) => lines,
+                Err(e) => {
+                    println!("Error reading {}: {}", path_str, e);
+                    skipped_count += matches.len();
+                    continue;
+                }
+            };
+
+            let mut changes = Vec::new();
+            let mut i = 0;
+            while i < matches.len() {
+                let line = matches[i].line;
+                let line_matches: Vec<&Match> = matches[i..].iter().take_while(|m| m.line == line).cloned().collect();
+                i += line_matches.len();
+
+                let is_unchanged = lines.get((line - 1) as usize).map(|text| *text == line_matches[0].text).unwrap_or(false);
+                if !is_unchanged {
+                    skipped_count += line_matches.len();
+                    continue;
+                }
+                match get_new_line(search, line_matches.as_ref(), replacement) {
+                    Ok(new_line) => {
+                        replaced_count += line_matches.len();
+                        changes.push((line, new_line));
+                    },
+                    Err(e) => {
+                        println!("Error replacing in {}: {}", path_str, e);
+                        skipped_count += line_matches.len();
+                    }
+                }
+            }
+            if changes.len() > 0 {
+                client.set_lines(changes.as_ref());
+                file_count += 1;
+            }
+        }
+
+        if let Some(buffer) = current {
+            client.show_buffer(buffer.number);
+        }
+        (replaced_count, file_count, skipped_count)
+    };
+
+    // the results are out of date now, so clear them
+    state.search = None;
+    state.search_store.clear();
+    let mut text = format!("Replaced {} matches in {} files, which still need to be saved", replaced_count, file_count);
+    if skipped_count > 0 {
+        text.push_str(format!(" ({} skipped because they changed)", skipped_count).as_ref());
+    }
+    state.search_label.set_text(text.as_ref());
+}
+
+unsafe fn take_error(error: *mut GError) -> String {
+    let msg = String::from_utf8_lossy(CStr::from_ptr((*error).message).to_bytes()).into_owned();
+    g_error_free(error);
+    msg
+}
+
+unsafe fn take_string(c_str: *mut c_char) -> String {
+    let s = String::from_utf8_lossy(CStr::from_ptr(c_str).to_bytes()).into_owned();
+    g_free(c_str as *mut c_void);
+    s
+}
+
+// a compiled glib regex, which is immutable and safe to share between threads
+pub struct Regex {
+    regex: *mut c_void
+}
+
+unsafe impl Send for Regex {}
+
+impl Regex {
+    pub fn new(pattern: &str, is_case_sensitive: bool) -> Result<Regex, String> {
+        let c_pattern = try!(CString::new(pattern).map_err(|_| "Invalid pattern".to_string()));
+        let options = G_REGEX_OPTIMIZE | if is_case_sensitive { 0 } else { G_REGEX_CASELESS };
+        let mut error: *mut GError = 0 as *mut GError;
+        let regex = unsafe { g_regex_new(c_pattern.as_ptr(), options, 0, &mut error) };
+        if regex.is_null() {
+            Err(if error.is_null() { "Invalid pattern".to_string() } else { unsafe { take_error(error) } })
+        } else {
+            Ok(Regex { regex: regex })
+        }
+    }
+
+    // escapes the text so it's matched literally
+    pub fn escape(text: &str) -> String {
+        unsafe { take_string(g_regex_escape_string(text.as_ptr() as *const c_char, text.len() as c_int)) }
+    }
+
+    // returns the byte ranges of the non-empty matches in the text
+    pub fn find_all(&self, text: &str) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        unsafe {
+            let mut match_info: *mut c_void = 0 as *mut c_void;
+            g_regex_match_full(self.regex, text.as_ptr() as *const c_char, text.len() as isize,
+                               0, 0, &mut match_info, 0 as *mut *mut GError);
+            while g_match_info_matches(match_info) != 0 {
+                let mut start: c_int = 0;
+                let mut end: c_int = 0;
+                if g_match_info_fetch_pos(match_info, 0, &mut start, &mut end) != 0 && end > start {
+                    ranges.push((start as usize, end as usize));
+                }
+                g_match_info_next(match_info, 0 as *mut *mut GError);
+            }
+            g_match_info_free(match_info);
+        }
+        ranges
+    }
+
+    // expands references like \1 in the replacement for the match starting at the given byte
+    pub fn expand(&self, text: &str, start: usize, replacement: &str) -> Result<String, String> {
+        let c_replacement = try!(CString::new(replacement).map_err(|_| "Invalid replacement".to_string()));
+        unsafe {
+            let mut match_info: *mut c_void = 0 as *mut c_void;
+            g_regex_match_full(self.regex, text.as_ptr() as *const c_char, text.len() as isize,
+                               start as c_int, 0, &mut match_info, 0 as *mut *mut GError);
+            let mut match_start: c_int = -1;
+            let mut match_end: c_int = -1;
+            let result = if g_match_info_matches(match_info) == 0 ||
+                g_match_info_fetch_pos(match_info, 0, &mut match_start, &mut match_end) == 0 ||
+                match_start as usize != start
+            {
+                Err("The text no longer matches".to_string())
+            } else {
+                let mut error: *mut GError = 0 as *mut GError;
+                let expanded = g_match_info_expand_references(match_info, c_replacement.as_ptr(), &mut error);
+                if expanded.is_null() {
+                    Err(if error.is_null() { "Invalid replacement".to_string() } else { take_error(error) })
+                } else {
+                    Ok(take_string(expanded))
+                }
+            };
+            g_match_info_free(match_info);
+            result
+        }
+    }
+}
+
+impl Clone for Regex {
+    fn clone(&self) -> Regex {
+        Regex { regex: unsafe { g_regex_ref(self.regex) } }
+    }
+}
+
+impl Drop for Regex {
+    fn drop(&mut self) {
+        unsafe { g_regex_unref(self.regex) };
+    }
+}
+
+pub type ToggleCallback<'a> = Box<FnMut(String) + 'a>;
+
+extern "C" fn cell_toggled_trampoline(_: *mut c_void, path: *const c_char, user_data: *mut c_void) {
+    let callback: &mut ToggleCallback = unsafe { mem::transmute(user_data) };
+    let path_str = unsafe { String::from_utf8_lossy(CStr::from_ptr(path).to_bytes()).into_owned() };
+    callback(path_str);
+}
+
+extern "C" fn cell_toggled_destroy(user_data: *mut c_void, _: *mut c_void) {
+    let _: Box<ToggleCallback> = unsafe { mem::transmute(user_data) };
+}
+
+// the callback gets the tree path of the row whose toggle cell was clicked, as a string
+pub fn connect_cell_toggled<'a>(renderer: ::ui::WidgetPtr, callback: ToggleCallback<'a>) {
+    let user_data: *mut c_void = unsafe { mem::transmute(Box::new(callback)) };
+    let c_signal = CString::new("toggled").unwrap();
+    unsafe {
+        g_signal_connect_data(
+            renderer,
+            c_signal.as_ptr(),
+            cell_toggled_trampoline as *const c_void,
+            user_data,
+            cell_toggled_destroy,
+            0
+        );
+    }
+}
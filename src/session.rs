@@ -0,0 +1,135 @@
+use rustc_serialize::{Encodable, json};
+use std::fs::{self, PathExt};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+static SESSIONS_DIR : &'static str = "sessions";
+// vim's sessions cover the buffers, cursor and scroll positions and splits, but not the options or mappings
+static SESSION_OPTIONS : &'static str = "buffers,folds,tabpages,winsize";
+
+static FNV_OFFSET_BASIS : u64 = 0xcbf29ce484222325;
+static FNV_PRIME : u64 = 0x100000001b3;
+
+// the parts of a session that live in the gui, saved next to the one nvim writes
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Session {
+    projects: Vec<String>,
+    builder: Option<String>,
+    pub layout: Option<::utils::WindowLayout>
+}
+
+fn get_sorted_projects(state: &::utils::State) -> Vec<String> {
+    let mut projects: Vec<String> = state.projects.iter().cloned().collect();
+    projects.sort();
+    projects
+}
+
+// FNV-1a, which unlike the std hashers is guaranteed to give the same result in every version
+fn hash_str(s: &str) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for b in s.bytes() {
+        hash = (hash ^ (b as u64)).wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// each set of projects gets its own session, named after a hash of their paths
+fn get_session_path(state: &::utils::State, extension: &str) -> PathBuf {
+    let hash = hash_str(get_sorted_projects(state).connect("\n").as_ref());
+    ::utils::get_home_dir().join(::utils::DATA_DIR).join(SESSIONS_DIR)
+        .join(format!("{:016x}.{}", hash, extension))
+}
+
+// nvim writes its part of the session as it quits, however it's quit, so it needs to know where
+pub fn start_sessions(state: &::utils::State, client: &mut ::nvim::Client) {
+    let sessions_dir = ::utils::get_home_dir().join(::utils::DATA_DIR).join(SESSIONS_DIR);
+    if let Err(e) = fs::create_dir_all(&sessions_dir) {
+        println!("Error creating sessions dir: {}", e);
+    }
+    // the user's own sessionoptions are only swapped out while the session is written
+    client.command(format!("au VimLeavePre * if exists('g:soak_session') | \
+                            let g:soak_sessionoptions = &sessionoptions | let &sessionoptions = '{}' | \
+                            execute 'mksession! ' . fnameescape(g:soak_session) | \
+                            let &sessionoptions = g:soak_sessionoptions | endif", SESSION_OPTIONS).as_ref());
+    update_session_path(state, client);
+}
+
+// called whenever the set of projects changes
+pub fn update_session_path(state: &::utils::State, client: &mut ::nvim::Client) {
+    if let Some(path_str) = get_session_path(state, "vim").to_str() {
+        client.set_string_var("soak_session", path_str);
+    }
+}
+
+// reopens the buffers and splits from last time, returning false if there weren't any
+pub fn restore_buffers(state: &::utils::State, client: &mut ::nvim::Client) -> bool {
+    let path = get_session_path(state, "vim");
+    match path.to_str() {
+        Some(path_str) if path.exists() => {
+            client.command(format!("silent! source {}", ::nvim::escape_path(path_str)).as_ref());
+            true
+        },
+        _ => false
+    }
+}
+
+pub fn read_session(state: &::utils::State) -> Option<Session> {
+    let session_path = get_session_path(state, "json");
+    if let Some(mut f) = fs::File::open(&session_path).ok() {
+        let mut json_str = String::new();
+        match f.read_to_string(&mut json_str) {
+            Ok(_) => {
+                match json::decode(json_str.as_ref()) {
+                    Ok(object) => return Some(object),
+                    Err(e) => println!("Error decoding session: {}", e)
+                }
+            },
+            Err(e) => println!("Error reading session: {}", e)
+        }
+    }
+    None
+}
+
+pub fn write_session(state: &::utils::State) {
+    let session = Session {
+        projects: get_sorted_projects(state),
+        builder: state.visible_builder.as_ref().and_then(|path| path.to_str()).map(|s| s.to_string()),
+        layout: state.window_layout.clone()
+    };
+
+    let mut json_str = String::new();
+    {
+        let mut encoder = json::Encoder::new_pretty(&mut json_str);
+        session.encode(&mut encoder).ok().expect("Error encoding session.");
+    }
+
+    let session_path = get_session_path(state, "json");
+    if let Some(mut f) = fs::File::create(&session_path).ok() {
+        match f.write(json_str.as_bytes()) {
+            Ok(_) => {},
+            Err(e) => println!("Error writing session: {}", e)
+        };
+    }
+}
+
+pub fn apply_session(state: &mut ::utils::State, session: &Session) {
+    // the builder follows the selection, so it only needs help when the selection is somewhere else
+    if let Some(ref builder_str) = session.builder {
+        let is_in_builder = state.selection.as_ref().map(|path_str| Path::new(path_str).starts_with(builder_str)).unwrap_or(false);
+        if state.projects.contains(builder_str) && !is_in_builder {
+            state.selection = Some(builder_str.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash_str;
+
+    #[test]
+    fn hashes_match_fnv_1a() {
+        assert_eq!(hash_str(""), 0xcbf29ce484222325);
+        assert_eq!(hash_str("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash_str("foobar"), 0x85944171f73967e8);
+    }
+}
@@ -0,0 +1,205 @@
+use gtk::traits::*;
+use gtk::{self, signals, widgets};
+use libc::{c_char, c_uint, c_ulong, c_void};
+use std::cmp;
+use std::ffi::CString;
+use std::fs::PathExt;
+use std::mem;
+use std::path::Path;
+
+#[link(name = "gobject-2.0")]
+extern "C" {
+    fn g_signal_connect_data (
+        instance: *mut c_void,
+        detailed_signal: *const c_char,
+        handler: *const c_void,
+        data: *mut c_void,
+        destroy_data: extern "C" fn(*mut c_void, *mut c_void),
+        connect_flags: c_uint
+    ) -> c_ulong;
+}
+
+pub enum TabEvent {
+    Close(i64)
+}
+
+// a tab for one of nvim's listed buffers. the pages are empty, since the buffer is shown in the editor below.
+pub struct Tab {
+    pub number: i64,
+    pub name: String,
+    pub is_modified: bool,
+    page: widgets::Box
+}
+
+fn get_label(name: &str) -> String {
+    let path = Path::new(name);
+    if name.len() == 0 || path.is_dir() {
+        "[No Name]".to_string()
+    } else {
+        path.file_name().and_then(|s| s.to_str()).unwrap_or(name).to_string()
+    }
+}
+
+fn add_tab(state: &::utils::State, notebook: &mut widgets::Notebook, buffer: &::nvim::Buffer) -> Tab {
+    let page = widgets::Box::new(gtk::Orientation::Vertical, 0).unwrap();
+    let label_text = if buffer.is_modified {
+        format!("{} \u{25cf}", get_label(buffer.name.as_ref()))
+    } else {
+        get_label(buffer.name.as_ref())
+    };
+    let label = widgets::Label::new(label_text.as_ref()).unwrap();
+    let event_box = widgets::EventBox::new().unwrap();
+    event_box.set_visible_window(false);
+    event_box.add(&label);
+    event_box.set_tooltip_text(buffer.name.as_ref());
+
+    // the tab is switched by the notebook itself, so only the middle button is handled here
+    let notifier = state.tab_notifier.clone();
+    let number = buffer.number;
+    event_box.connect(signals::ButtonPressEvent::new(&mut move |event| {
+        if unsafe { (*event).button } == 2 {
+            notifier.send(TabEvent::Close(number));
+            return true;
+        }
+        false
+    }));
+    event_box.show_all();
+
+    notebook.append_page(&page, Some(&event_box));
+    notebook.set_tab_reorderable(&page, true);
+
+    Tab {
+        number: buffer.number,
+        name: buffer.name.clone(),
+        is_modified: buffer.is_modified,
+        page: page
+    }
+}
+
+// keeps the tabs in the order they were opened or dragged into, and only rebuilds them if something changed
+pub fn update_tabs(state: &mut ::utils::State, client: &mut ::nvim::Client, notebook: &mut widgets::Notebook) {
+    let buffers = match client.buffer_list() {
+        Ok(buffers) => buffers,
+        Err(e) => {
+            println!("Error listing buffers: {}", e);
+            return;
+        }
+    };
+    let current = client.current_buffer().ok().map(|buffer| buffer.number);
+
+    let mut ordered_buffers = Vec::new();
+    for tab in state.buffer_tabs.iter() {
+        if let Some(buffer) = buffers.iter().find(|buffer| buffer.number == tab.number) {
+            ordered_buffers.push(buffer);
+        }
+    }
+    for buffer in buffers.iter() {
+        if !state.buffer_tabs.iter().any(|tab| tab.number == buffer.number) {
+            ordered_buffers.push(buffer);
+        }
+    }
+
+    state.is_refreshing_tabs = true;
+
+    let is_changed = ordered_buffers.len() != state.buffer_tabs.len() ||
+        ordered_buffers.iter().zip(state.buffer_tabs.iter()).any(|(buffer, tab)| {
+            buffer.number != tab.number || buffer.name != tab.name || buffer.is_modified != tab.is_modified
+        });
+    if is_changed {
+        while notebook.get_n_pages() > 0 {
+            notebook.remove_page(-1);
+        }
+        let mut tabs = Vec::new();
+        for buffer in ordered_buffers.iter() {
+            tabs.push(add_tab(state, notebook, buffer));
+        }
+        state.buffer_tabs = tabs;
+        notebook.show_all();
+    }
+
+    if let Some(i) = state.buffer_tabs.iter().position(|tab| Some(tab.number) == current) {
+        notebook.set_current_page(i as i32);
+    }
+
+    state.is_refreshing_tabs = false;
+}
+
+pub fn switch_tab(state: &::utils::State, client: &mut ::nvim::Client, page_num: u32) {
+    if state.is_refreshing_tabs {
+        return;
+    }
+    if let Some(tab) = state.buffer_tabs.get(page_num as usize) {
+        client.show_buffer(tab.number);
+    }
+}
+
+// the notebook has already moved the page, so the tab just needs to follow it
+pub fn move_tab(state: &mut ::utils::State, page_ptr: ::ui::WidgetPtr, page_num: u32) {
+    if let Some(i) = state.buffer_tabs.iter().position(|tab| tab.page.unwrap_widget() as ::ui::WidgetPtr == page_ptr) {
+        let tab = state.buffer_tabs.remove(i);
+        let new_i = cmp::min(page_num as usize, state.buffer_tabs.len());
+        state.buffer_tabs.insert(new_i, tab);
+    }
+}
+
+pub fn close_tab(state: &::utils::State, client: &mut ::nvim::Client, number: i64) {
+    if let Some(tab) = state.buffer_tabs.iter().find(|tab| tab.number == number) {
+        if !tab.is_modified {
+            client.close_buffer_number(number);
+            return;
+        }
+        match ::ui::ask_to_save(state, &[get_label(tab.name.as_ref())]) {
+            ::ui::SaveChoice::Save => {
+                // saving shows the buffer, so go back to the one that was showing before
+                let current = client.current_buffer().ok();
+                client.write_buffer(number);
+                if let Some(buffer) = current {
+                    if buffer.number != number {
+                        client.show_buffer(buffer.number);
+                    }
+                }
+                // the write can fail, and then the buffer is kept so the changes aren't lost
+                match client.is_buffer_modified(number) {
+                    Ok(false) => client.close_buffer_number(number),
+                    Ok(true) => println!("Error saving {}, so it wasn't closed", tab.name),
+                    Err(e) => println!("Error checking {} was saved: {}", tab.name, e)
+                }
+            },
+            ::ui::SaveChoice::Discard => client.delete_buffer(number),
+            ::ui::SaveChoice::Cancel => {}
+        }
+    }
+}
+
+pub fn handle_event(state: &::utils::State, client: &mut ::nvim::Client, event: TabEvent) {
+    match event {
+        TabEvent::Close(number) => close_tab(state, client, number)
+    }
+}
+
+pub type PageCallback<'a> = Box<FnMut(::ui::WidgetPtr, u32) + 'a>;
+
+extern "C" fn page_trampoline(_: *mut c_void, child: *mut c_void, page_num: c_uint, user_data: *mut c_void) {
+    let callback: &mut PageCallback = unsafe { mem::transmute(user_data) };
+    callback(child, page_num as u32);
+}
+
+extern "C" fn page_destroy(user_data: *mut c_void, _: *mut c_void) {
+    let _: Box<PageCallback> = unsafe { mem::transmute(user_data) };
+}
+
+// for notebook signals like switch-page and page-reordered, which pass the page's child and its number
+pub fn connect_page_signal<'a>(notebook: ::ui::WidgetPtr, signal: &str, callback: PageCallback<'a>) {
+    let user_data: *mut c_void = unsafe { mem::transmute(Box::new(callback)) };
+    let c_signal = CString::new(signal).unwrap();
+    unsafe {
+        g_signal_connect_data(
+            notebook,
+            c_signal.as_ptr(),
+            page_trampoline as *const c_void,
+            user_data,
+            page_destroy,
+            0
+        );
+    }
+}
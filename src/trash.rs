@@ -0,0 +1,126 @@
+use libc::{c_char, c_int, c_long};
+use libc::types::os::arch::c95::size_t;
+use std::env;
+use std::ffi::CString;
+use std::fs::{self, PathExt};
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+#[repr(C)]
+struct Tm {
+    tm_sec: c_int,
+    tm_min: c_int,
+    tm_hour: c_int,
+    tm_mday: c_int,
+    tm_mon: c_int,
+    tm_year: c_int,
+    tm_wday: c_int,
+    tm_yday: c_int,
+    tm_isdst: c_int,
+    tm_gmtoff: c_long,
+    tm_zone: *const c_char
+}
+
+extern "C" {
+    fn time (t: *mut c_long) -> c_long;
+    fn localtime_r (t: *const c_long, result: *mut Tm) -> *mut Tm;
+    fn strftime (s: *mut c_char, max: size_t, format: *const c_char, tm: *const Tm) -> size_t;
+}
+
+// a file or directory that was moved into the trash, so it can be put back
+pub struct TrashedItem {
+    pub original_path: PathBuf,
+    pub trash_path: PathBuf,
+    pub info_path: PathBuf,
+    // buffers that were open inside it, to reopen when it's restored
+    pub buffers: Vec<String>
+}
+
+// follows the freedesktop.org trash spec, which file managers understand too
+fn get_trash_dir() -> PathBuf {
+    match env::var("XDG_DATA_HOME") {
+        Ok(ref data_home) if data_home.len() > 0 => PathBuf::from(data_home).join("Trash"),
+        _ => ::utils::get_home_dir().join(".local").join("share").join("Trash")
+    }
+}
+
+// claims a free name by creating its info file, which fails if another program got there first
+fn create_info_file(files_dir: &Path, info_dir: &Path, name: &str) -> io::Result<(String, fs::File)> {
+    let mut n = 1;
+    loop {
+        let free_name = if n == 1 { name.to_string() } else { format!("{}.{}", name, n) };
+        n += 1;
+        if files_dir.join(&free_name).exists() {
+            continue;
+        }
+        let info_path = info_dir.join(format!("{}.trashinfo", free_name));
+        match fs::OpenOptions::new().write(true).create_new(true).open(&info_path) {
+            Ok(f) => return Ok((free_name, f)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+pub fn trash_path(path: &Path, buffers: Vec<String>) -> io::Result<TrashedItem> {
+    let trash_dir = get_trash_dir();
+    let files_dir = trash_dir.join("files");
+    let info_dir = trash_dir.join("info");
+    try!(fs::create_dir_all(&files_dir));
+    try!(fs::create_dir_all(&info_dir));
+
+    let path_str = try!(path.to_str().ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Invalid path")));
+    let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("file");
+    let (free_name, mut f) = try!(create_info_file(&files_dir, &info_dir, name));
+    let trash_path = files_dir.join(&free_name);
+    let info_path = info_dir.join(format!("{}.trashinfo", free_name));
+
+    // the info file is written first, as the spec requires, and stores the path url-escaped
+    let info = format!("[Trash Info]\nPath={}\nDeletionDate={}\n",
+                       ::utils::escape_uri_path(path_str), format_local_time("%Y-%m-%dT%H:%M:%S"));
+    if let Err(e) = f.write_all(info.as_bytes()) {
+        fs::remove_file(&info_path).ok();
+        return Err(e);
+    }
+
+    if let Err(e) = ::utils::move_path(path, &trash_path) {
+        fs::remove_file(&info_path).ok();
+        return Err(e);
+    }
+
+    Ok(TrashedItem {
+        original_path: path.to_path_buf(),
+        trash_path: trash_path,
+        info_path: info_path,
+        buffers: buffers
+    })
+}
+
+pub fn restore(item: &TrashedItem) -> io::Result<()> {
+    if item.original_path.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Something else is already there"));
+    }
+    if let Some(parent_path) = item.original_path.parent() {
+        try!(fs::create_dir_all(parent_path));
+    }
+    try!(::utils::move_path(&item.trash_path, &item.original_path));
+    fs::remove_file(&item.info_path).ok();
+    Ok(())
+}
+
+// formats the current local time with strftime
+fn format_local_time(format: &str) -> String {
+    let c_format = match CString::new(format) {
+        Ok(c_format) => c_format,
+        Err(_) => return String::new()
+    };
+    let mut buf = [0u8; 64];
+    let len = unsafe {
+        let now = time(0 as *mut c_long);
+        let mut tm: Tm = mem::zeroed();
+        localtime_r(&now, &mut tm);
+        strftime(buf.as_mut_ptr() as *mut c_char, buf.len() as size_t, c_format.as_ptr(), &tm)
+    };
+    String::from_utf8_lossy(&buf[..len as usize]).into_owned()
+}
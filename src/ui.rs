@@ -1,11 +1,116 @@
+use glib;
 use gtk::traits::*;
-use gtk::widgets;
-use std::cmp::Ordering;
-use std::collections::HashSet;
+use gtk::{self, widgets};
+use libc::{c_char, c_int, c_uint, c_ulong, c_void};
+use std::cmp::{self, Ordering};
+use std::collections::{BTreeSet, HashSet};
+use std::ffi::{CStr, CString};
 use std::fs::{self, PathExt};
+use std::mem;
+use std::num::FromPrimitive;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 
+static GDK_BUTTON1_MASK : c_uint = 1 << 8;
+static GDK_ACTION_COPY : c_uint = 2;
+static GDK_ACTION_MOVE : c_uint = 4;
+
+static URI_LIST_TARGET : &'static str = "text/uri-list";
+
+#[repr(C)]
+struct GdkRectangle {
+    x: c_int,
+    y: c_int,
+    width: c_int,
+    height: c_int
+}
+
+#[repr(C)]
+struct TargetEntry {
+    target: *const c_char,
+    flags: c_uint,
+    info: c_uint
+}
+
+#[repr(C)]
+struct TreeIterRaw {
+    stamp: c_int,
+    user_data: *mut c_void,
+    user_data2: *mut c_void,
+    user_data3: *mut c_void
+}
+
+#[link(name = "glib-2.0")]
+extern "C" {
+    fn g_strfreev (str_array: *mut *mut c_char);
+    fn g_free (mem: *mut c_void);
+}
+
+#[link(name = "gobject-2.0")]
+extern "C" {
+    fn g_signal_connect_data (
+        instance: *mut c_void,
+        detailed_signal: *const c_char,
+        handler: *const c_void,
+        data: *mut c_void,
+        destroy_data: extern "C" fn(*mut c_void, *mut c_void),
+        connect_flags: c_uint
+    ) -> c_ulong;
+    fn g_signal_stop_emission_by_name (instance: *mut c_void, detailed_signal: *const c_char);
+}
+
+#[link(name = "gdk-3")]
+extern "C" {
+    fn gdk_atom_intern (atom_name: *const c_char, only_if_exists: c_int) -> *mut c_void;
+    fn gdk_screen_get_default () -> *mut c_void;
+    fn gdk_screen_get_monitor_at_point (screen: *mut c_void, x: c_int, y: c_int) -> c_int;
+    fn gdk_screen_get_monitor_workarea (screen: *mut c_void, monitor_num: c_int, dest: *mut GdkRectangle);
+}
+
+#[link(name = "gtk-3")]
+extern "C" {
+    fn gtk_clipboard_get (selection: *mut c_void) -> *mut c_void;
+    fn gtk_clipboard_set_text (clipboard: *mut c_void, text: *const c_char, len: c_int);
+    fn gtk_tree_view_enable_model_drag_source (
+        tree_view: *mut c_void,
+        start_button_mask: c_uint,
+        targets: *const TargetEntry,
+        n_targets: c_int,
+        actions: c_uint
+    );
+    fn gtk_tree_view_enable_model_drag_dest (
+        tree_view: *mut c_void,
+        targets: *const TargetEntry,
+        n_targets: c_int,
+        actions: c_uint
+    );
+    fn gtk_tree_view_get_dest_row_at_pos (
+        tree_view: *mut c_void,
+        drag_x: c_int,
+        drag_y: c_int,
+        path: *mut *mut c_void,
+        pos: *mut c_int
+    ) -> c_int;
+    fn gtk_tree_view_get_model (tree_view: *mut c_void) -> *mut c_void;
+    fn gtk_tree_view_get_path_at_pos (
+        tree_view: *mut c_void,
+        x: c_int,
+        y: c_int,
+        path: *mut *mut c_void,
+        column: *mut *mut c_void,
+        cell_x: *mut c_int,
+        cell_y: *mut c_int
+    ) -> c_int;
+    fn gtk_tree_view_set_cursor (tree_view: *mut c_void, path: *mut c_void, focus_column: *mut c_void, start_editing: c_int);
+    fn gtk_tree_model_get_iter (model: *mut c_void, iter: *mut TreeIterRaw, path: *mut c_void) -> c_int;
+    fn gtk_tree_model_get (model: *mut c_void, iter: *mut TreeIterRaw, ...);
+    fn gtk_tree_path_free (path: *mut c_void);
+    fn gtk_selection_data_set_uris (selection_data: *mut c_void, uris: *mut *mut c_char) -> c_int;
+    fn gtk_selection_data_get_uris (selection_data: *mut c_void) -> *mut *mut c_char;
+    fn gtk_drag_finish (context: *mut c_void, success: c_int, del: c_int, time: c_uint);
+    fn gtk_window_is_maximized (window: *mut c_void) -> c_int;
+}
+
 fn path_sorter(a: &PathBuf, b: &PathBuf) -> Ordering {
     if let Some(a_os_str) = a.deref().file_name() {
         if let Some(b_os_str) = b.deref().file_name() {
@@ -24,52 +129,188 @@ fn sort_string_paths(paths: &HashSet<String>) -> Vec<PathBuf> {
     paths_vec
 }
 
+// projects can't be renamed, since they're only references to directories
+pub fn can_rename(state: &::utils::State, path_str: &String) -> bool {
+    !state.projects.contains(path_str)
+}
+
 pub fn update_project_buttons(state: &::utils::State) {
     if let Some(path_str) = ::utils::get_selected_path(state) {
-        let is_project = state.projects.contains(&path_str);
-        let path = Path::new(&path_str);
-        state.rename_button.set_sensitive(!path.is_dir());
-        state.remove_button.set_sensitive(!path.is_dir() || is_project);
+        state.rename_button.set_sensitive(can_rename(state, &path_str));
+        state.remove_button.set_sensitive(true);
     } else {
         state.rename_button.set_sensitive(false);
         state.remove_button.set_sensitive(false);
     }
 }
 
-fn add_node(state: &::utils::State, node: &Path, parent: Option<&widgets::TreeIter>) {
+// hidden and ignored rows are only added when they're being shown, and then drawn in this color
+static DIMMED_COLOR : &'static str = "gray";
+
+fn set_node(state: &::utils::State, iter: &widgets::TreeIter, leaf_str: &str, full_path_str: &str) {
+    state.tree_store.set_string(iter, 0, leaf_str);
+    state.tree_store.set_string(iter, 1, full_path_str);
+    set_visible(state, iter, true);
+}
+
+// the tree view shows the store through a filter, which hides rows whose visible column is false
+fn set_visible(state: &::utils::State, iter: &widgets::TreeIter, is_visible: bool) {
+    let mut value = glib::Value::new();
+    value.init(glib::Type::Bool);
+    value.set_boolean(is_visible);
+    state.tree_store.set_value(iter, 6, &value);
+}
+
+// converts a row in the store to a path in the tree view, which is None if it's filtered out
+fn get_view_path(state: &::utils::State, iter: &widgets::TreeIter) -> Option<widgets::TreePath> {
+    state.tree_model.get_path(iter).and_then(|path| state.tree_filter.convert_child_path_to_path(&path))
+}
+
+// converts a row from the tree view to a row in the store
+pub fn get_store_iter(state: &::utils::State, view_iter: &widgets::TreeIter) -> widgets::TreeIter {
+    let mut iter = widgets::TreeIter::new().unwrap();
+    state.tree_filter.convert_iter_to_child_iter(&mut iter, view_iter);
+    iter
+}
+
+fn set_git_status(state: &::utils::State, iter: &widgets::TreeIter, path: &Path) {
+    match ::git::get_status(state, path) {
+        Some(status) => {
+            state.tree_store.set_string(iter, 4, status.label());
+            state.tree_store.set_string(iter, 5, status.color());
+        },
+        None => {
+            // an unset color leaves the row's default foreground
+            let mut value = glib::Value::new();
+            value.init(glib::Type::String);
+            state.tree_store.set_string(iter, 4, "");
+            state.tree_store.set_value(iter, 5, &value);
+        }
+    }
+}
+
+fn is_dimmed_row(state: &::utils::State, iter: &widgets::TreeIter) -> bool {
+    state.tree_model.get_value(iter, 3).get_string().map(|s| s.len() > 0).unwrap_or(false)
+}
+
+// everything inside a hidden or ignored directory is dimmed too
+fn is_hidden_or_ignored(matcher: &mut ::ignore::Matcher, node: &Path, is_parent_dimmed: bool) -> bool {
+    if is_parent_dimmed {
+        return true;
+    }
+    match node.file_name().and_then(|s| s.to_str()) {
+        Some(leaf_str) => leaf_str.starts_with(".") || matcher.is_ignored(node, node.is_dir()),
+        None => true
+    }
+}
+
+fn add_children(
+    state: &::utils::State,
+    matcher: &mut ::ignore::Matcher,
+    node: &Path,
+    iter: &widgets::TreeIter,
+    is_dimmed: bool)
+{
+    if node.is_dir() {
+        match fs::read_dir(node) {
+            Ok(child_iter) => {
+                let mut child_vec = Vec::new();
+                for child in child_iter {
+                    if let Ok(dir_entry) = child {
+                        child_vec.push(dir_entry.path());
+                    }
+                }
+                child_vec.sort_by(path_sorter);
+                for child in child_vec.iter() {
+                    add_node(state, matcher, child.deref(), Some(iter), is_dimmed);
+                }
+            },
+            Err(e) => println!("Error updating tree: {}", e)
+        }
+    }
+}
+
+fn add_node(
+    state: &::utils::State,
+    matcher: &mut ::ignore::Matcher,
+    node: &Path,
+    parent: Option<&widgets::TreeIter>,
+    is_parent_dimmed: bool)
+{
     let mut iter = widgets::TreeIter::new().unwrap();
 
     if let Some(full_path_str) = node.to_str() {
         if let Some(leaf_os_str) = node.file_name() {
             if let Some(leaf_str) = leaf_os_str.to_str() {
-                if !leaf_str.starts_with(".") {
+                // the project roots are always shown
+                let is_dimmed = parent.is_some() && is_hidden_or_ignored(matcher, node, is_parent_dimmed);
+                if !is_dimmed || state.show_ignored {
                     state.tree_store.append(&mut iter, parent);
-                    state.tree_store.set_string(&iter, 0, leaf_str);
-                    state.tree_store.set_string(&iter, 1, full_path_str);
-
-                    if node.is_dir() {
-                        match fs::read_dir(node) {
-                            Ok(child_iter) => {
-                                let mut child_vec = Vec::new();
-                                for child in child_iter {
-                                    if let Ok(dir_entry) = child {
-                                        child_vec.push(dir_entry.path());
-                                    }
-                                }
-                                child_vec.sort_by(path_sorter);
-                                for child in child_vec.iter() {
-                                    add_node(state, child.deref(), Some(&iter));
-                                }
-                            },
-                            Err(e) => println!("Error updating tree: {}", e)
+                    set_node(state, &iter, leaf_str, full_path_str);
+                    set_git_status(state, &iter, node);
+                    if is_dimmed {
+                        state.tree_store.set_string(&iter, 3, DIMMED_COLOR);
+                    }
+                    if parent.is_none() {
+                        if let Some(builder) = state.builders.get(&node.to_path_buf()) {
+                            state.tree_store.set_string(&iter, 2, builder.status.icon_name());
                         }
                     }
+                    add_children_or_placeholder(state, matcher, node, &iter, full_path_str, is_dimmed);
                 }
             }
         }
     }
 }
 
+// directories are only read once they're expanded, so unexpanded ones get an empty
+// placeholder row that makes them expandable
+fn add_children_or_placeholder(
+    state: &::utils::State,
+    matcher: &mut ::ignore::Matcher,
+    node: &Path,
+    iter: &widgets::TreeIter,
+    full_path_str: &str,
+    is_dimmed: bool)
+{
+    if node.is_dir() {
+        if state.expansions.contains(&full_path_str.to_string()) {
+            add_children(state, matcher, node, iter, is_dimmed);
+        } else {
+            let mut placeholder = widgets::TreeIter::new().unwrap();
+            state.tree_store.append(&mut placeholder, Some(iter));
+            set_node(state, &placeholder, "", "");
+        }
+    }
+}
+
+fn get_placeholder(state: &::utils::State, iter: &widgets::TreeIter) -> Option<widgets::TreeIter> {
+    let mut child = widgets::TreeIter::new().unwrap();
+    if state.tree_model.iter_children(&mut child, Some(iter)) {
+        if let Some(child_str) = state.tree_model.get_value(&child, 1).get_string() {
+            if child_str.len() == 0 {
+                return Some(child);
+            }
+        }
+    }
+    None
+}
+
+pub fn load_children(state: &mut ::utils::State, iter: &widgets::TreeIter) {
+    if let Some(placeholder) = get_placeholder(state, iter) {
+        if let Some(path_str) = state.tree_model.get_value(iter, 1).get_string() {
+            let path = Path::new(&path_str);
+            if let Some(project_path) = ::utils::get_root_path(state, path) {
+                let mut matcher = ::utils::get_matcher(state, &project_path);
+                let is_dimmed = is_dimmed_row(state, iter);
+                // add the real children first so the row doesn't collapse when the placeholder goes away
+                add_children(state, &mut matcher, path, iter, is_dimmed);
+            }
+        }
+        state.tree_store.remove(&placeholder);
+    }
+}
+
 fn expand_nodes(
     state: &mut ::utils::State,
     tree: &mut widgets::TreeView,
@@ -82,14 +323,14 @@ fn expand_nodes(
             if let Some(path_str) = state.tree_model.get_value(&iter, 1).get_string() {
                 if let Some(selection_str) = state.selection.clone() {
                     if path_str == selection_str {
-                        if let Some(path) = state.tree_model.get_path(&iter) {
+                        if let Some(path) = get_view_path(state, &iter) {
                             tree.set_cursor(&path, None, false);
                         }
                     }
                 }
 
                 if state.expansions.contains(&path_str) {
-                    if let Some(path) = state.tree_model.get_path(&iter) {
+                    if let Some(path) = get_view_path(state, &iter) {
                         tree.expand_row(&path, false);
                         expand_nodes(state, tree, Some(&iter));
                     }
@@ -109,12 +350,684 @@ pub fn update_project_tree(state: &mut ::utils::State, tree: &mut widgets::TreeV
     state.tree_store.clear();
 
     for path in sort_string_paths(&state.projects).iter() {
-        add_node(state, path, None);
+        let mut matcher = ::utils::get_matcher(state, path);
+        add_node(state, &mut matcher, path, None, false);
     }
 
     expand_nodes(state, tree, None);
 
+    if state.filter_query.len() > 0 {
+        apply_filter(state, tree);
+    }
+
+    update_project_buttons(state);
+
+    state.is_refreshing_tree = false;
+}
+
+// reads just the directories leading to files in the index that match, so the filter
+// can find files that haven't been loaded without reading the whole tree.
+// rows that are already loaded are matched as they are.
+fn load_index_matches(state: &mut ::utils::State, query: &str) {
+    let mut dirs = BTreeSet::new();
+    for (project_path, files) in state.file_index.iter() {
+        let base_path = project_path.parent().unwrap_or(project_path);
+        for file in files.iter() {
+            let is_match = match file.relative_from(base_path) {
+                Some(relative_path) => {
+                    let components: Vec<&str> = relative_path.iter().filter_map(|s| s.to_str()).collect();
+                    ::fuzzy::is_path_match(query, components.as_ref())
+                },
+                None => false
+            };
+            if !is_match {
+                continue;
+            }
+            let mut parent = file.parent();
+            while let Some(dir) = parent {
+                if !dir.starts_with(project_path) || !dirs.insert(dir.to_path_buf()) {
+                    break;
+                }
+                parent = dir.parent();
+            }
+        }
+    }
+
+    // sorted, so each dir's row has been loaded by the time its children are looked up
+    for dir in dirs.iter() {
+        if let Some(iter) = find_row(state, dir) {
+            load_children(state, &iter);
+        }
+    }
+}
+
+// rows are matched against their path starting from the project's name
+fn get_filter_path(state: &::utils::State, path: &Path) -> PathBuf {
+    if let Some(root_path) = ::utils::get_root_path(state, path) {
+        if let Some(parent_path) = root_path.parent() {
+            if let Some(relative_path) = path.relative_from(parent_path) {
+                return relative_path.to_path_buf();
+            }
+        }
+    }
+    path.to_path_buf()
+}
+
+// hides the rows that don't match and have no children that match, returning whether any are left
+fn filter_rows(state: &::utils::State, parent: Option<&widgets::TreeIter>, query: &str) -> bool {
+    let mut iter = widgets::TreeIter::new().unwrap();
+    let mut has_match = false;
+
+    if state.tree_model.iter_children(&mut iter, parent) {
+        loop {
+            if let Some(path_str) = state.tree_model.get_value(&iter, 1).get_string() {
+                let relative_path = get_filter_path(state, Path::new(&path_str));
+                let components: Vec<&str> = relative_path.iter().filter_map(|s| s.to_str()).collect();
+
+                let is_match = ::fuzzy::is_path_match(query, components.as_ref());
+                let has_child_match = filter_rows(state, Some(&iter), query);
+                set_visible(state, &iter, is_match || has_child_match);
+                has_match = has_match || is_match || has_child_match;
+            }
+
+            if !state.tree_model.iter_next(&mut iter) {
+                break;
+            }
+        }
+    }
+
+    has_match
+}
+
+fn show_all_rows(state: &::utils::State, parent: Option<&widgets::TreeIter>) {
+    let mut iter = widgets::TreeIter::new().unwrap();
+
+    if state.tree_model.iter_children(&mut iter, parent) {
+        loop {
+            set_visible(state, &iter, true);
+            show_all_rows(state, Some(&iter));
+
+            if !state.tree_model.iter_next(&mut iter) {
+                break;
+            }
+        }
+    }
+}
+
+fn apply_filter(state: &mut ::utils::State, tree: &mut widgets::TreeView) {
+    let query = state.filter_query.clone();
+    load_index_matches(state, query.as_ref());
+    filter_rows(state, None, query.as_ref());
+    state.tree_filter.refilter();
+    tree.expand_all();
+}
+
+// while there's a query the matching rows are all expanded, and once it's cleared
+// the tree goes back to the expansions it had before
+// the index has changed, so rows for newly indexed matches may need to be loaded
+pub fn update_filter(state: &mut ::utils::State, tree: &mut widgets::TreeView) {
+    if state.filter_query.len() > 0 {
+        state.is_refreshing_tree = true;
+        apply_filter(state, tree);
+        state.is_refreshing_tree = false;
+        update_selection(state, tree);
+    }
+}
+
+pub fn filter_tree(state: &mut ::utils::State, tree: &mut widgets::TreeView, query: &str) {
+    let query = query.trim().to_string();
+    if query == state.filter_query {
+        return;
+    }
+    state.is_refreshing_tree = true;
+    state.filter_query = query;
+
+    if state.filter_query.len() > 0 {
+        apply_filter(state, tree);
+    } else {
+        show_all_rows(state, None);
+        state.tree_filter.refilter();
+        tree.collapse_all();
+        expand_nodes(state, tree, None);
+    }
+
+    state.is_refreshing_tree = false;
+
+    update_selection(state, tree);
+}
+
+// finds a row by walking down from the project that contains the path
+fn find_row(state: &::utils::State, path: &Path) -> Option<widgets::TreeIter> {
+    let mut parent : Option<widgets::TreeIter> = None;
+
+    'outer: loop {
+        let mut iter = widgets::TreeIter::new().unwrap();
+        if !state.tree_model.iter_children(&mut iter, parent.as_ref()) {
+            return None;
+        }
+        loop {
+            if let Some(row_str) = state.tree_model.get_value(&iter, 1).get_string() {
+                let row_path = Path::new(&row_str);
+                if row_path == path {
+                    return Some(iter);
+                } else if path.starts_with(row_path) {
+                    parent = Some(iter);
+                    continue 'outer;
+                }
+            }
+            if !state.tree_model.iter_next(&mut iter) {
+                return None;
+            }
+        }
+    }
+}
+
+fn insert_node(state: &mut ::utils::State, tree: &mut widgets::TreeView, path: &Path) {
+    let project_path = match ::utils::get_root_path(state, path) {
+        Some(project_path) => project_path,
+        None => return
+    };
+    if find_row(state, path).is_some() {
+        return;
+    }
+
+    // the parent may be missing if it's hidden or ignored itself,
+    // and if it hasn't been loaded yet the new file will show up when it is
+    let parent = match path.parent().and_then(|parent_path| find_row(state, parent_path)) {
+        Some(parent) => parent,
+        None => return
+    };
+    if get_placeholder(state, &parent).is_some() {
+        return;
+    }
+
+    let mut matcher = ::utils::get_matcher(state, &project_path);
+    let is_dimmed = is_hidden_or_ignored(&mut matcher, path, is_dimmed_row(state, &parent));
+    if is_dimmed && !state.show_ignored {
+        return;
+    }
+
+    if let Some(full_path_str) = path.to_str() {
+        if let Some(leaf_str) = path.file_name().and_then(|s| s.to_str()) {
+            // keep the children in the same order as a full rebuild by inserting before the first one that comes after it
+            let mut iter = widgets::TreeIter::new().unwrap();
+            let mut sibling = widgets::TreeIter::new().unwrap();
+            let mut has_sibling = state.tree_model.iter_children(&mut sibling, Some(&parent));
+            let path_buf = path.to_path_buf();
+            while has_sibling {
+                if let Some(sibling_str) = state.tree_model.get_value(&sibling, 1).get_string() {
+                    if path_sorter(&PathBuf::from(&sibling_str), &path_buf) == Ordering::Greater {
+                        break;
+                    }
+                }
+                has_sibling = state.tree_model.iter_next(&mut sibling);
+            }
+            if has_sibling {
+                state.tree_store.insert_before(&mut iter, Some(&parent), Some(&sibling));
+            } else {
+                state.tree_store.append(&mut iter, Some(&parent));
+            }
+
+            set_node(state, &iter, leaf_str, full_path_str);
+            set_git_status(state, &iter, path);
+            if is_dimmed {
+                state.tree_store.set_string(&iter, 3, DIMMED_COLOR);
+            }
+            add_children_or_placeholder(state, &mut matcher, path, &iter, full_path_str, is_dimmed);
+
+            if state.expansions.contains(&full_path_str.to_string()) {
+                if let Some(tree_path) = get_view_path(state, &iter) {
+                    tree.expand_row(&tree_path, false);
+                    expand_nodes(state, tree, Some(&iter));
+                }
+            }
+        }
+    }
+}
+
+fn remove_node(state: &mut ::utils::State, path: &Path) {
+    if let Some(path_str) = path.to_str() {
+        if state.projects.contains(&path_str.to_string()) {
+            return;
+        }
+    }
+    if let Some(iter) = find_row(state, path) {
+        state.tree_store.remove(&iter);
+    }
+}
+
+// applies changes from the file watcher without rebuilding the tree, so expansions and selection stay put
+pub fn apply_changes(state: &mut ::utils::State, tree: &mut widgets::TreeView, changes: Vec<::watcher::Change>) {
+    let needs_rescan = changes.iter().any(|change| {
+        match *change {
+            ::watcher::Change::Created(ref path) |
+            ::watcher::Change::Removed(ref path) |
+            ::watcher::Change::Modified(ref path) => ::ignore::is_ignore_file(path) || ::utils::is_settings_file(path),
+            ::watcher::Change::Moved(ref from, ref to) => {
+                ::ignore::is_ignore_file(from) || ::utils::is_settings_file(from) ||
+                    ::ignore::is_ignore_file(to) || ::utils::is_settings_file(to)
+            },
+            ::watcher::Change::Rescan => true
+        }
+    });
+    if needs_rescan {
+        // the watcher's own ignore rules may have changed too
+        for path_str in state.projects.iter() {
+            ::projects::watch_project(state, path_str);
+        }
+        update_project_tree(state, tree);
+        return;
+    }
+
+    state.is_refreshing_tree = true;
+
+    for change in changes.into_iter() {
+        match change {
+            ::watcher::Change::Created(path) => insert_node(state, tree, &path),
+            ::watcher::Change::Removed(path) => remove_node(state, &path),
+            ::watcher::Change::Moved(from, to) => {
+                // moved before the row is re-added, so it comes back expanded and selected
+                ::utils::move_expansions(state, &from, &to);
+                remove_node(state, &from);
+                insert_node(state, tree, &to);
+            },
+            ::watcher::Change::Modified(_) | ::watcher::Change::Rescan => {}
+        }
+    }
+
+    state.is_refreshing_tree = false;
+
+    // the selected row may have just been created, such as after a move
+    update_selection(state, tree);
+}
+
+pub fn update_selection(state: &mut ::utils::State, tree: &mut widgets::TreeView) {
+    state.is_refreshing_tree = true;
+
+    // setting the cursor scrolls to it, so leave it alone if it's already there
+    if state.selection.is_some() && ::utils::get_selected_path(state) != state.selection {
+        if let Some(selection_str) = state.selection.clone() {
+            if let Some(iter) = find_row(state, Path::new(&selection_str)) {
+                if let Some(path) = get_view_path(state, &iter) {
+                    tree.set_cursor(&path, None, false);
+                }
+            }
+        }
+    }
+
     update_project_buttons(state);
 
     state.is_refreshing_tree = false;
 }
+
+pub fn update_build_status(state: &::utils::State) {
+    let mut iter = widgets::TreeIter::new().unwrap();
+
+    if state.tree_model.iter_children(&mut iter, None) {
+        loop {
+            if let Some(path_str) = state.tree_model.get_value(&iter, 1).get_string() {
+                if let Some(builder) = state.builders.get(&PathBuf::from(&path_str)) {
+                    state.tree_store.set_string(&iter, 2, builder.status.icon_name());
+                }
+            }
+
+            if !state.tree_model.iter_next(&mut iter) {
+                break;
+            }
+        }
+    }
+}
+
+fn update_git_status_rows(state: &::utils::State, parent: Option<&widgets::TreeIter>) {
+    let mut iter = widgets::TreeIter::new().unwrap();
+
+    if state.tree_model.iter_children(&mut iter, parent) {
+        loop {
+            if let Some(path_str) = state.tree_model.get_value(&iter, 1).get_string() {
+                // skip the placeholders in unloaded directories
+                if path_str.len() > 0 {
+                    set_git_status(state, &iter, Path::new(&path_str));
+                    update_git_status_rows(state, Some(&iter));
+                }
+            }
+
+            if !state.tree_model.iter_next(&mut iter) {
+                break;
+            }
+        }
+    }
+}
+
+pub fn update_git_status(state: &::utils::State) {
+    update_git_status_rows(state, None);
+}
+
+pub fn update_diagnostics(state: &mut ::utils::State) {
+    state.diagnostics_store.clear();
+
+    let project_path = match state.visible_builder {
+        Some(ref path) => path.clone(),
+        None => return
+    };
+
+    if let Some(builder) = state.builders.get(&project_path) {
+        for diag in builder.diagnostics.iter() {
+            let mut iter = widgets::TreeIter::new().unwrap();
+            state.diagnostics_store.append(&mut iter, None);
+
+            if let Some(span) = diag.primary_span() {
+                let relative_path = Path::new(&span.file_name).relative_from(&project_path)
+                    .and_then(|p| p.to_str()).unwrap_or(span.file_name.as_ref()).to_string();
+                let text = format!("{}\n{}:{}:{}",
+                                   diag.summary(), relative_path, span.line_start, span.column_start);
+                state.diagnostics_store.set_string(&iter, 0, text.as_ref());
+                state.diagnostics_store.set_string(&iter, 1, span.file_name.as_ref());
+                state.diagnostics_store.set_string(&iter, 2, span.line_start.to_string().as_ref());
+                state.diagnostics_store.set_string(&iter, 3, span.column_start.to_string().as_ref());
+
+                for other_span in diag.spans.iter().filter(|s| !s.is_primary) {
+                    if let Some(ref label) = other_span.label {
+                        let mut child = widgets::TreeIter::new().unwrap();
+                        state.diagnostics_store.append(&mut child, Some(&iter));
+                        let child_text = format!("{}:{}: {}", other_span.line_start, other_span.column_start, label);
+                        state.diagnostics_store.set_string(&child, 0, child_text.as_ref());
+                        state.diagnostics_store.set_string(&child, 1, other_span.file_name.as_ref());
+                        state.diagnostics_store.set_string(&child, 2, other_span.line_start.to_string().as_ref());
+                        state.diagnostics_store.set_string(&child, 3, other_span.column_start.to_string().as_ref());
+                    }
+                }
+
+                for suggestion in diag.suggestions.iter() {
+                    let mut child = widgets::TreeIter::new().unwrap();
+                    state.diagnostics_store.append(&mut child, Some(&iter));
+                    state.diagnostics_store.set_string(&child, 0, suggestion.as_ref());
+                    state.diagnostics_store.set_string(&child, 1, span.file_name.as_ref());
+                    state.diagnostics_store.set_string(&child, 2, span.line_start.to_string().as_ref());
+                    state.diagnostics_store.set_string(&child, 3, span.column_start.to_string().as_ref());
+                }
+            }
+        }
+    }
+}
+
+pub enum SaveChoice {
+    Save,
+    Discard,
+    Cancel
+}
+
+// asks what to do with buffers that have unsaved changes, listing their names
+pub fn ask_to_save(state: &::utils::State, names: &[String]) -> SaveChoice {
+    let list: Vec<String> = names.iter().map(|name| format!("<b>{}</b>", ::utils::escape_markup(name.as_ref()))).collect();
+    let text = if names.len() == 1 {
+        format!("Save the changes to {}?", list[0])
+    } else {
+        format!("Save the changes to these files?\n\n{}", list.connect("\n"))
+    };
+    let mut choice = SaveChoice::Cancel;
+    if let Some(dialog) = widgets::MessageDialog::new_with_markup(
+        Some(state.window.clone()),
+        gtk::DialogFlags::Modal,
+        gtk::MessageType::Question,
+        gtk::ButtonsType::None,
+        text.as_ref()
+    ) {
+        dialog.add_button("Cancel", gtk::ResponseType::Cancel as i32);
+        dialog.add_button("Discard", gtk::ResponseType::No as i32);
+        dialog.add_button(if names.len() == 1 { "Save" } else { "Save All" }, gtk::ResponseType::Yes as i32);
+        choice = match FromPrimitive::from_i32(dialog.run()) {
+            Some(gtk::ResponseType::Yes) => SaveChoice::Save,
+            Some(gtk::ResponseType::No) => SaveChoice::Discard,
+            _ => SaveChoice::Cancel
+        };
+        dialog.destroy();
+    }
+    choice
+}
+
+// a maximized window keeps the size and position it had before, so it can be unmaximized to them next time
+pub fn get_window_layout(
+    previous: Option<&::utils::WindowLayout>,
+    window: &widgets::Window,
+    window_pane: &widgets::Paned,
+    resizer: &widgets::Paned) -> ::utils::WindowLayout
+{
+    let is_maximized = is_window_maximized(window.unwrap_widget() as WidgetPtr);
+    let (width, height) = window.get_size();
+    let (x, y) = window.get_position();
+    let editor_height_pct = if height > 0 {
+        resizer.get_position() as f32 / height as f32
+    } else {
+        ::utils::EDITOR_HEIGHT_PCT
+    };
+    let (width, height, x, y) = match previous {
+        Some(layout) if is_maximized => (layout.width, layout.height, layout.x, layout.y),
+        _ => (width, height, x, y)
+    };
+    ::utils::WindowLayout {
+        width: width,
+        height: height,
+        x: x,
+        y: y,
+        is_maximized: is_maximized,
+        left_pane_width: window_pane.get_position(),
+        editor_height_pct: editor_height_pct
+    }
+}
+
+// without a saved layout, the window is left centered at its default size.
+// a saved one is kept on the monitor it would open on, in case that one got smaller or went away.
+pub fn apply_window_layout(
+    layout: Option<&::utils::WindowLayout>,
+    window: &widgets::Window,
+    window_pane: &widgets::Paned,
+    resizer: &widgets::Paned)
+{
+    let (mut width, mut height) = (::utils::WINDOW_WIDTH, ::utils::WINDOW_HEIGHT);
+    let (mut area_width, mut area_height) = (width, height);
+    if let Some(layout) = layout {
+        let (mut x, mut y) = (layout.x, layout.y);
+        width = layout.width;
+        height = layout.height;
+        area_width = width;
+        area_height = height;
+        if let Some((area_x, area_y, w, h)) = get_monitor_workarea(x, y) {
+            area_width = w;
+            area_height = h;
+            width = cmp::max(cmp::min(width, area_width), 1);
+            height = cmp::max(cmp::min(height, area_height), 1);
+            x = cmp::max(cmp::min(x, area_x + area_width - width), area_x);
+            y = cmp::max(cmp::min(y, area_y + area_height - height), area_y);
+        }
+        window.move_(x, y);
+    }
+    window.resize(width, height);
+
+    // the panes are sized for the window they'll end up in
+    let is_maximized = layout.map(|layout| layout.is_maximized).unwrap_or(false);
+    let (full_width, full_height) = if is_maximized { (area_width, area_height) } else { (width, height) };
+    let editor_height_pct = layout.map(|layout| layout.editor_height_pct).unwrap_or(::utils::EDITOR_HEIGHT_PCT);
+    resizer.set_position((editor_height_pct.max(0.0).min(1.0) * (full_height as f32)) as i32);
+    if let Some(layout) = layout {
+        window_pane.set_position(cmp::max(cmp::min(layout.left_pane_width, full_width), 0));
+    }
+    if is_maximized {
+        window.maximize();
+    }
+}
+
+pub fn copy_to_clipboard(text: &str) {
+    if let Ok(c_text) = CString::new(text) {
+        let c_name = CString::new("CLIPBOARD").unwrap();
+        unsafe {
+            let clipboard = gtk_clipboard_get(gdk_atom_intern(c_name.as_ptr(), 0));
+            gtk_clipboard_set_text(clipboard, c_text.as_ptr(), -1);
+        }
+    }
+}
+
+// a raw GtkWidget pointer, for the things the gtk bindings don't cover
+pub type WidgetPtr = *mut c_void;
+
+fn is_window_maximized(window: WidgetPtr) -> bool {
+    unsafe { gtk_window_is_maximized(window) != 0 }
+}
+
+// returns the x, y, width and height of the usable part of the monitor at (or nearest to) the given point
+pub fn get_monitor_workarea(x: i32, y: i32) -> Option<(i32, i32, i32, i32)> {
+    unsafe {
+        let screen = gdk_screen_get_default();
+        if screen.is_null() {
+            return None;
+        }
+        let monitor = gdk_screen_get_monitor_at_point(screen, x, y);
+        let mut area = GdkRectangle { x: 0, y: 0, width: 0, height: 0 };
+        gdk_screen_get_monitor_workarea(screen, monitor, &mut area);
+        Some((area.x, area.y, area.width, area.height))
+    }
+}
+
+pub type DragGetCallback<'a> = Box<FnMut() -> Vec<String> + 'a>;
+pub type DropCallback<'a> = Box<FnMut(i32, i32, Vec<String>) -> bool + 'a>;
+
+// lets rows be dragged out of the tree and uris be dropped onto it
+pub fn enable_tree_drag_and_drop(tree: WidgetPtr) {
+    let c_target = CString::new(URI_LIST_TARGET).unwrap();
+    let targets = [TargetEntry { target: c_target.as_ptr(), flags: 0, info: 0 }];
+    unsafe {
+        gtk_tree_view_enable_model_drag_source(
+            tree, GDK_BUTTON1_MASK, targets.as_ptr(), 1, GDK_ACTION_COPY | GDK_ACTION_MOVE
+        );
+        gtk_tree_view_enable_model_drag_dest(tree, targets.as_ptr(), 1, GDK_ACTION_COPY | GDK_ACTION_MOVE);
+    }
+}
+
+// returns the string in the given column of the row that a drop at x, y would land on
+pub fn get_tree_drop_value(tree: WidgetPtr, x: i32, y: i32, column: i32) -> Option<String> {
+    unsafe {
+        let mut path: *mut c_void = 0 as *mut c_void;
+        let mut pos: c_int = 0;
+        if gtk_tree_view_get_dest_row_at_pos(tree, x, y, &mut path, &mut pos) == 0 {
+            return None;
+        }
+        let model = gtk_tree_view_get_model(tree);
+        let mut iter: TreeIterRaw = mem::zeroed();
+        let mut value: *mut c_char = 0 as *mut c_char;
+        if gtk_tree_model_get_iter(model, &mut iter, path) != 0 {
+            gtk_tree_model_get(model, &mut iter, column, &mut value, -1 as c_int);
+        }
+        gtk_tree_path_free(path);
+        if value.is_null() {
+            return None;
+        }
+        let value_str = String::from_utf8_lossy(CStr::from_ptr(value).to_bytes()).into_owned();
+        g_free(value as *mut c_void);
+        Some(value_str)
+    }
+}
+
+// selects the row at x, y in the tree's own coordinates, returning false if there isn't one
+pub fn select_tree_row_at_pos(tree: WidgetPtr, x: i32, y: i32) -> bool {
+    unsafe {
+        let mut path: *mut c_void = 0 as *mut c_void;
+        let null = 0 as *mut c_void;
+        if gtk_tree_view_get_path_at_pos(tree, x, y, &mut path, null as *mut *mut c_void,
+                                         null as *mut c_int, null as *mut c_int) == 0 {
+            return false;
+        }
+        gtk_tree_view_set_cursor(tree, path, null, 0);
+        gtk_tree_path_free(path);
+        true
+    }
+}
+
+extern "C" fn drag_data_get_trampoline(
+    widget: *mut c_void,
+    _: *mut c_void,
+    selection_data: *mut c_void,
+    _: c_uint,
+    _: c_uint,
+    user_data: *mut c_void)
+{
+    let callback: &mut DragGetCallback = unsafe { mem::transmute(user_data) };
+    let c_uris: Vec<CString> = callback().into_iter().filter_map(|uri| CString::new(uri).ok()).collect();
+    let mut uri_ptrs: Vec<*mut c_char> = c_uris.iter().map(|uri| uri.as_ptr() as *mut c_char).collect();
+    uri_ptrs.push(0 as *mut c_char);
+    let c_signal = CString::new("drag-data-get").unwrap();
+    unsafe {
+        gtk_selection_data_set_uris(selection_data, uri_ptrs.as_mut_ptr());
+        g_signal_stop_emission_by_name(widget, c_signal.as_ptr());
+    }
+}
+
+extern "C" fn drag_data_get_destroy(user_data: *mut c_void, _: *mut c_void) {
+    let _: Box<DragGetCallback> = unsafe { mem::transmute(user_data) };
+}
+
+extern "C" fn drag_data_received_trampoline(
+    widget: *mut c_void,
+    context: *mut c_void,
+    x: c_int,
+    y: c_int,
+    selection_data: *mut c_void,
+    _: c_uint,
+    time: c_uint,
+    user_data: *mut c_void)
+{
+    let callback: &mut DropCallback = unsafe { mem::transmute(user_data) };
+    let mut uris = Vec::new();
+    unsafe {
+        let c_uris = gtk_selection_data_get_uris(selection_data);
+        if !c_uris.is_null() {
+            let mut i = 0;
+            while !(*c_uris.offset(i)).is_null() {
+                uris.push(String::from_utf8_lossy(CStr::from_ptr(*c_uris.offset(i)).to_bytes()).into_owned());
+                i += 1;
+            }
+            g_strfreev(c_uris);
+        }
+    }
+    let is_success = uris.len() > 0 && callback(x, y, uris);
+    // the files are moved by the callback, so the tree shouldn't delete anything itself
+    let c_signal = CString::new("drag-data-received").unwrap();
+    unsafe {
+        gtk_drag_finish(context, if is_success { 1 } else { 0 }, 0, time);
+        g_signal_stop_emission_by_name(widget, c_signal.as_ptr());
+    }
+}
+
+extern "C" fn drag_data_received_destroy(user_data: *mut c_void, _: *mut c_void) {
+    let _: Box<DropCallback> = unsafe { mem::transmute(user_data) };
+}
+
+// the callback returns the uris being dragged out of the widget
+pub fn connect_drag_data_get<'a>(widget: WidgetPtr, callback: DragGetCallback<'a>) {
+    let user_data: *mut c_void = unsafe { mem::transmute(Box::new(callback)) };
+    let c_signal = CString::new("drag-data-get").unwrap();
+    unsafe {
+        g_signal_connect_data(
+            widget,
+            c_signal.as_ptr(),
+            drag_data_get_trampoline as *const c_void,
+            user_data,
+            drag_data_get_destroy,
+            0
+        );
+    }
+}
+
+// the callback gets the drop position and the uris dropped, and returns whether it used them
+pub fn connect_drag_data_received<'a>(widget: WidgetPtr, callback: DropCallback<'a>) {
+    let user_data: *mut c_void = unsafe { mem::transmute(Box::new(callback)) };
+    let c_signal = CString::new("drag-data-received").unwrap();
+    unsafe {
+        g_signal_connect_data(
+            widget,
+            c_signal.as_ptr(),
+            drag_data_received_trampoline as *const c_void,
+            user_data,
+            drag_data_received_destroy,
+            0
+        );
+    }
+}
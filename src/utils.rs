@@ -1,8 +1,9 @@
 use gtk::widgets;
 use rustc_serialize::{Encodable, json};
+use std::cell::RefCell;
 use std::env;
-use std::collections::{HashMap, HashSet};
-use std::io::{Read, Write};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{self, Read, Write};
 use std::fs::{self, PathExt};
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
@@ -10,6 +11,11 @@ use std::path::{Path, PathBuf};
 pub static WINDOW_WIDTH : i32 = 1242;
 pub static WINDOW_HEIGHT : i32 = 768;
 pub static EDITOR_HEIGHT_PCT : f32 = 0.70;
+pub static DIAGNOSTICS_POSITION : i32 = 600;
+pub static PALETTE_WIDTH : i32 = 600;
+pub static PALETTE_HEIGHT : i32 = 400;
+pub static SEARCH_WIDTH : i32 = 800;
+pub static SEARCH_HEIGHT : i32 = 600;
 pub static MIN_FONT_SIZE : i32 = 0;
 pub static MAX_FONT_SIZE : i32 = 50;
 
@@ -23,6 +29,7 @@ pub static CONFIG_FILE : &'static str = ".soakrc";
 pub static CONFIG_CONTENT : &'static str = include_str!("../resources/soakrc");
 pub static PREFS_FILE : &'static str = "prefs.json";
 pub static SETTINGS_FILE : &'static str = "settings.json";
+pub static PROJECT_SETTINGS_FILE : &'static str = ".solidoak.json";
 pub static NO_WINDOW_FLAG : &'static str = "-nw";
 
 pub struct Resource {
@@ -110,19 +117,72 @@ pub static DATA_CONTENT : &'static [Resource] = &[
 ];
 
 pub struct State<'a> {
+    pub settings: Settings,
     pub projects: HashSet<String>,
     pub expansions: HashSet<String>,
     pub selection: Option<String>,
     pub easy_mode: bool,
+    pub show_ignored: bool,
     pub font_size: i32,
-    pub builders: HashMap<PathBuf, (widgets::VteTerminal, i32)>,
+    pub build_profiles: HashMap<String, String>,
+    pub builders: HashMap<PathBuf, ::builders::Builder>,
+    pub visible_builder: Option<PathBuf>,
+    pub build_notifier: ::ffi::Notifier<::builders::BuildEvent>,
+    pub watcher: ::watcher::Watcher,
+    pub git_statuses: HashMap<PathBuf, ::git::Statuses>,
+    pub git_pending: HashSet<PathBuf>,
+    pub git_stale: HashSet<PathBuf>,
+    pub git_notifier: ::ffi::Notifier<::git::StatusUpdate>,
+    pub trashed: Vec<::trash::TrashedItem>,
+    pub file_index: HashMap<PathBuf, BTreeSet<PathBuf>>,
+    pub index_notifier: ::ffi::Notifier<::index::IndexUpdate>,
+    // the most recent scan started, and the one each project is waiting on
+    pub index_generation: u64,
+    pub index_pending: HashMap<PathBuf, u64>,
+    pub recent_files: Vec<String>,
+    // each project's own settings file, parsed, until the watcher sees it change
+    pub project_settings: RefCell<HashMap<PathBuf, Option<ProjectSettings>>>,
+    pub window_layout: Option<WindowLayout>,
+    pub palette_store: &'a widgets::TreeStore,
+    pub palette_model: &'a widgets::TreeModel,
+    pub palette_selection: &'a widgets::TreeSelection,
+    pub search: Option<::search::Search>,
+    pub search_notifier: ::ffi::Notifier<::search::SearchEvent>,
+    pub search_store: &'a widgets::TreeStore,
+    pub search_model: &'a widgets::TreeModel,
+    pub search_selection: &'a widgets::TreeSelection,
+    pub search_label: &'a widgets::Label,
+    pub buffer_tabs: Vec<::tabs::Tab>,
+    pub tab_notifier: ::ffi::Notifier<::tabs::TabEvent>,
     pub window: &'a widgets::Window,
     pub tree_store: &'a widgets::TreeStore,
     pub tree_model: &'a widgets::TreeModel,
+    pub tree_filter: &'a widgets::TreeModelFilter,
+    pub filter_model: &'a widgets::TreeModel,
     pub tree_selection: &'a widgets::TreeSelection,
+    pub filter_query: String,
+    pub diagnostics_store: &'a widgets::TreeStore,
+    pub diagnostics_model: &'a widgets::TreeModel,
+    pub diagnostics_selection: &'a widgets::TreeSelection,
+    pub profile_combo: &'a widgets::ComboBoxText,
     pub rename_button: &'a widgets::Button,
     pub remove_button: &'a widgets::Button,
-    pub is_refreshing_tree: bool
+    pub is_refreshing_tree: bool,
+    pub is_refreshing_profiles: bool,
+    pub is_refreshing_tabs: bool
+}
+
+// the editor's share of the window's height is kept rather than the divider's position,
+// so it still looks right if the window comes back smaller
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+pub struct WindowLayout {
+    pub width: i32,
+    pub height: i32,
+    pub x: i32,
+    pub y: i32,
+    pub is_maximized: bool,
+    pub left_pane_width: i32,
+    pub editor_height_pct: f32
 }
 
 #[derive(RustcDecodable, RustcEncodable)]
@@ -131,7 +191,11 @@ struct Prefs {
     expansions: Vec<String>,
     selection: Option<String>,
     easy_mode: bool,
-    font_size: i32
+    font_size: i32,
+    build_profiles: Option<BTreeMap<String, String>>,
+    show_ignored: Option<bool>,
+    recent_files: Option<Vec<String>>,
+    window_layout: Option<WindowLayout>
 }
 
 #[derive(RustcDecodable, RustcEncodable)]
@@ -140,6 +204,8 @@ pub struct KeySettings {
     pub import: Option<String>,
     pub rename: Option<String>,
     pub remove: Option<String>,
+    pub go_to_file: Option<String>,
+    pub find_in_files: Option<String>,
 
     pub run: Option<String>,
     pub build: Option<String>,
@@ -150,14 +216,71 @@ pub struct KeySettings {
     pub save: Option<String>,
     pub undo: Option<String>,
     pub redo: Option<String>,
+    pub prev_error: Option<String>,
+    pub next_error: Option<String>,
     pub font_dec: Option<String>,
     pub font_inc: Option<String>,
     pub close: Option<String>
 }
 
+#[derive(RustcDecodable, RustcEncodable, Clone)]
+pub struct CommandSettings {
+    pub args: Vec<String>,
+    pub env: Option<BTreeMap<String, String>>,
+    pub working_dir: Option<String>,
+    pub use_profile: Option<bool>
+}
+
+#[derive(RustcDecodable, RustcEncodable, Clone)]
+pub struct ProfileSettings {
+    pub name: String,
+    pub release: Option<bool>,
+    pub features: Option<Vec<String>>,
+    pub target: Option<String>,
+    pub extra_args: Option<Vec<String>>,
+    pub env: Option<BTreeMap<String, String>>
+}
+
+#[derive(RustcDecodable, RustcEncodable, Clone)]
+pub struct BuildSettings {
+    pub run: Option<CommandSettings>,
+    pub build: Option<CommandSettings>,
+    pub test: Option<CommandSettings>,
+    pub clean: Option<CommandSettings>,
+    pub profiles: Option<Vec<ProfileSettings>>
+}
+
+#[derive(RustcDecodable, RustcEncodable, Clone)]
+pub struct EditorSettings {
+    pub tab_width: Option<u32>,
+    pub expand_tab: Option<bool>,
+    pub text_width: Option<u32>
+}
+
+// the settings that a project's own settings file can override
+#[derive(RustcDecodable, RustcEncodable, Clone)]
+pub struct ProjectSettings {
+    pub builds: Option<BuildSettings>,
+    pub env: Option<BTreeMap<String, String>>,
+    pub rustfmt_on_save: Option<bool>,
+    pub clippy: Option<bool>,
+    pub ignore: Option<Vec<String>>,
+    pub editor: Option<EditorSettings>
+}
+
 #[derive(RustcDecodable, RustcEncodable)]
 pub struct Settings {
-    pub keys: KeySettings
+    pub keys: KeySettings,
+    pub stop_signal: Option<String>,
+    pub stop_grace_period: Option<u32>,
+    pub builds: Option<BuildSettings>,
+    // overrides for individual projects, keyed by the project path
+    pub project_builds: Option<BTreeMap<String, BuildSettings>>,
+    pub env: Option<BTreeMap<String, String>>,
+    pub rustfmt_on_save: Option<bool>,
+    pub clippy: Option<bool>,
+    pub ignore: Option<Vec<String>>,
+    pub editor: Option<EditorSettings>
 }
 
 pub fn get_home_dir() -> PathBuf {
@@ -168,16 +291,149 @@ pub fn get_home_dir() -> PathBuf {
     }
 }
 
+// copies files and directories, including everything inside them
+pub fn copy_path(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        try!(fs::create_dir_all(to));
+        for child in try!(fs::read_dir(from)) {
+            let child_path = try!(child).path();
+            if let Some(name) = child_path.file_name() {
+                try!(copy_path(&child_path, &to.join(name)));
+            }
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+// the error from renaming across filesystems, which is the same on linux and os x
+static EXDEV : i32 = 18;
+
+// renaming only works within one filesystem, so anything else is copied and removed
+pub fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Ok(_) => return Ok(()),
+        Err(ref e) if e.raw_os_error() == Some(EXDEV) => {},
+        Err(e) => return Err(e)
+    }
+    try!(copy_path(from, to));
+    if from.is_dir() {
+        fs::remove_dir_all(from)
+    } else {
+        fs::remove_file(from)
+    }
+}
+
+// percent-escapes a path, as used in file uris and trash info files
+pub fn escape_uri_path(path_str: &str) -> String {
+    let mut escaped = String::new();
+    for b in path_str.bytes() {
+        match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => escaped.push(b as char),
+            _ => escaped.push_str(format!("%{:02X}", b).as_ref())
+        }
+    }
+    escaped
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'...b'9' => Some(b - b'0'),
+        b'a'...b'f' => Some(b - b'a' + 10),
+        b'A'...b'F' => Some(b - b'A' + 10),
+        _ => None
+    }
+}
+
+pub fn path_to_uri(path_str: &str) -> String {
+    format!("file://{}", escape_uri_path(path_str))
+}
+
+// only local file uris can be turned back into paths
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    if !uri.starts_with("file://") {
+        return None;
+    }
+    // skip the host, which is normally empty
+    let rest = &uri["file://".len()..];
+    let path_start = match rest.find('/') {
+        Some(i) => i,
+        None => return None
+    };
+    let bytes = rest[path_start..].as_bytes();
+    let mut unescaped = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                unescaped.push(hi * 16 + lo);
+                i += 3;
+                continue;
+            }
+        }
+        unescaped.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(unescaped).ok().map(|path_str| PathBuf::from(path_str))
+}
+
 fn get_prefs(state: &State) -> Prefs {
     Prefs {
         projects: state.projects.clone().into_iter().collect(),
         expansions: state.expansions.clone().into_iter().collect(),
         selection: state.selection.clone(),
         easy_mode: state.easy_mode,
-        font_size: state.font_size
+        font_size: state.font_size,
+        build_profiles: Some(state.build_profiles.clone().into_iter().collect()),
+        show_ignored: Some(state.show_ignored),
+        recent_files: Some(state.recent_files.clone()),
+        window_layout: state.window_layout.clone()
+    }
+}
+
+// paths are shown starting from the project's name, and buffers without a file get a placeholder
+pub fn get_display_path(state: &State, path: &Path) -> String {
+    if path == Path::new("") || path.is_dir() {
+        return "[No Name]".to_string();
+    }
+    let parent_path = get_root_path(state, path).and_then(|root_path| root_path.parent().map(|p| p.to_path_buf()));
+    match parent_path.as_ref().and_then(|parent_path| path.relative_from(parent_path)).and_then(|p| p.to_str()) {
+        Some(relative_str) => relative_str.to_string(),
+        None => path.to_str().unwrap_or("").to_string()
+    }
+}
+
+// for text shown in widgets that take pango markup
+pub fn escape_markup(text: &str) -> String {
+    text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;")
+}
+
+pub fn replace_path_prefix(path_str: &String, from: &Path, to: &Path) -> String {
+    match Path::new(path_str).relative_from(from) {
+        Some(relative_path) => {
+            let new_path = match relative_path.to_str() {
+                Some("") => to.to_path_buf(),
+                _ => to.join(relative_path)
+            };
+            new_path.to_str().map(|s| s.to_string()).unwrap_or(path_str.clone())
+        },
+        None => path_str.clone()
     }
 }
 
+// points the expansions and selection inside a moved directory at its new path
+pub fn move_expansions(state: &mut State, from: &Path, to: &Path) {
+    let expansions = state.expansions.iter().map(|expansion_str| {
+        replace_path_prefix(expansion_str, from, to)
+    }).collect();
+    state.expansions = expansions;
+    if let Some(selection_str) = state.selection.clone() {
+        state.selection = Some(replace_path_prefix(&selection_str, from, to));
+    }
+    write_prefs(state);
+}
+
 pub fn is_parent_path(parent_str: &String, child_str: &String) -> bool {
     let parent_ref: &str = parent_str.as_ref();
     child_str.starts_with(parent_ref) &&
@@ -187,8 +443,12 @@ pub fn is_parent_path(parent_str: &String, child_str: &String) -> bool {
 pub fn get_selected_path(state: &State) -> Option<String> {
     let mut iter = widgets::TreeIter::new().unwrap();
 
-    if state.tree_selection.get_selected(state.tree_model, &mut iter) {
-        state.tree_model.get_value(&iter, 1).get_string()
+    // the selection is in terms of the filtered model the tree view shows
+    if state.tree_selection.get_selected(state.filter_model, &mut iter) {
+        // placeholder rows in unloaded directories have an empty path
+        state.filter_model.get_value(&iter, 1).get_string().and_then(|path_str| {
+            if path_str.len() > 0 { Some(path_str) } else { None }
+        })
     } else {
         None
     }
@@ -274,6 +534,16 @@ pub fn read_prefs(state: &mut State) {
 
             state.selection = prefs.selection;
             state.easy_mode = prefs.easy_mode;
+            state.show_ignored = prefs.show_ignored.unwrap_or(false);
+            state.recent_files = prefs.recent_files.unwrap_or(Vec::new());
+            state.window_layout = prefs.window_layout;
+
+            state.build_profiles.clear();
+            if let Some(build_profiles) = prefs.build_profiles {
+                for (project_str, profile_name) in build_profiles.into_iter() {
+                    state.build_profiles.insert(project_str, profile_name);
+                }
+            }
 
             if (prefs.font_size >= MIN_FONT_SIZE) && (prefs.font_size <= MAX_FONT_SIZE) {
                 state.font_size = prefs.font_size;
@@ -282,6 +552,183 @@ pub fn read_prefs(state: &mut State) {
     }
 }
 
+fn get_command(args: &[&str], use_profile: bool) -> Option<CommandSettings> {
+    Some(CommandSettings {
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        env: None,
+        working_dir: None,
+        use_profile: Some(use_profile)
+    })
+}
+
+fn get_profile(name: &str, release: bool) -> ProfileSettings {
+    ProfileSettings {
+        name: name.to_string(),
+        release: Some(release),
+        features: None,
+        target: None,
+        extra_args: None,
+        env: None
+    }
+}
+
+// fills in anything missing from the overrides with the base settings
+pub fn merge_build_settings(base: &BuildSettings, overrides: &BuildSettings) -> BuildSettings {
+    BuildSettings {
+        run: overrides.run.clone().or(base.run.clone()),
+        build: overrides.build.clone().or(base.build.clone()),
+        test: overrides.test.clone().or(base.test.clone()),
+        clean: overrides.clean.clone().or(base.clean.clone()),
+        profiles: overrides.profiles.clone().or(base.profiles.clone())
+    }
+}
+
+fn merge_maps(base: &Option<BTreeMap<String, String>>, overrides: &Option<BTreeMap<String, String>>)
+    -> Option<BTreeMap<String, String>>
+{
+    match (base, overrides) {
+        (&Some(ref base_map), &Some(ref overrides_map)) => {
+            let mut map = base_map.clone();
+            for (key, value) in overrides_map.iter() {
+                map.insert(key.clone(), value.clone());
+            }
+            Some(map)
+        },
+        _ => overrides.clone().or(base.clone())
+    }
+}
+
+// values set by the project win, except that env vars are merged key by key
+// and ignore lists are combined, so a project can't un-ignore something globally ignored
+pub fn merge_project_settings(base: &ProjectSettings, overrides: &ProjectSettings) -> ProjectSettings {
+    let builds = match (&base.builds, &overrides.builds) {
+        (&Some(ref base_builds), &Some(ref overrides_builds)) =>
+            Some(merge_build_settings(base_builds, overrides_builds)),
+        _ => overrides.builds.clone().or(base.builds.clone())
+    };
+
+    let ignore = match (&base.ignore, &overrides.ignore) {
+        (&Some(ref base_ignore), &Some(ref overrides_ignore)) => {
+            let mut ignore = base_ignore.clone();
+            for pattern in overrides_ignore.iter() {
+                if !ignore.contains(pattern) {
+                    ignore.push(pattern.clone());
+                }
+            }
+            Some(ignore)
+        },
+        _ => overrides.ignore.clone().or(base.ignore.clone())
+    };
+
+    let editor = match (&base.editor, &overrides.editor) {
+        (&Some(ref base_editor), &Some(ref overrides_editor)) => Some(EditorSettings {
+            tab_width: overrides_editor.tab_width.or(base_editor.tab_width),
+            expand_tab: overrides_editor.expand_tab.or(base_editor.expand_tab),
+            text_width: overrides_editor.text_width.or(base_editor.text_width)
+        }),
+        _ => overrides.editor.clone().or(base.editor.clone())
+    };
+
+    ProjectSettings {
+        builds: builds,
+        env: merge_maps(&base.env, &overrides.env),
+        rustfmt_on_save: overrides.rustfmt_on_save.or(base.rustfmt_on_save),
+        clippy: overrides.clippy.or(base.clippy),
+        ignore: ignore,
+        editor: editor
+    }
+}
+
+pub fn is_settings_file(path: &Path) -> bool {
+    path.file_name().and_then(|s| s.to_str()) == Some(PROJECT_SETTINGS_FILE)
+}
+
+pub fn apply_settings_changes(state: &State, changes: &[::watcher::Change]) {
+    let mut cache = state.project_settings.borrow_mut();
+    for change in changes.iter() {
+        match *change {
+            ::watcher::Change::Created(ref path) |
+            ::watcher::Change::Removed(ref path) |
+            ::watcher::Change::Modified(ref path) => {
+                if is_settings_file(path) {
+                    if let Some(project_path) = path.parent() {
+                        cache.remove(project_path);
+                    }
+                }
+            },
+            ::watcher::Change::Moved(ref from, ref to) => {
+                for path in [from, to].iter() {
+                    if is_settings_file(path) {
+                        if let Some(project_path) = path.parent() {
+                            cache.remove(project_path);
+                        }
+                    }
+                }
+            },
+            ::watcher::Change::Rescan => cache.clear()
+        }
+    }
+}
+
+// a file that can't be read or decoded is treated as missing, so the global settings still apply
+fn read_project_settings(project_path: &Path) -> Option<ProjectSettings> {
+    let settings_path = project_path.join(PROJECT_SETTINGS_FILE);
+    if let Some(mut f) = fs::File::open(&settings_path).ok() {
+        let mut json_str = String::new();
+        if f.read_to_string(&mut json_str).is_ok() {
+            return json::decode(json_str.as_ref()).ok();
+        }
+    }
+    None
+}
+
+pub fn get_matcher(state: &State, project_path: &Path) -> ::ignore::Matcher {
+    let ignore = get_project_settings(state, project_path).ignore.unwrap_or(Vec::new());
+    ::ignore::Matcher::new(project_path, ignore.as_ref())
+}
+
+// the project in the tree that a path belongs to, which may not be the closest Cargo.toml.
+// when projects are nested, the innermost one wins.
+pub fn get_root_path(state: &State, path: &Path) -> Option<PathBuf> {
+    state.projects.iter()
+        .filter(|project_str| path.starts_with(project_str))
+        .max_by(|project_str| project_str.len())
+        .map(|project_str| PathBuf::from(project_str))
+}
+
+// applies the global settings, then the project's entry in them, then the project's own file
+pub fn get_project_settings(state: &State, project_path: &Path) -> ProjectSettings {
+    let settings = &state.settings;
+    let mut builds = settings.builds.clone();
+    if let Some(ref base_builds) = settings.builds {
+        if let Some(ref project_builds) = settings.project_builds {
+            if let Some(project_str) = project_path.to_str() {
+                if let Some(overrides) = project_builds.get(project_str) {
+                    builds = Some(merge_build_settings(base_builds, overrides));
+                }
+            }
+        }
+    }
+
+    let global = ProjectSettings {
+        builds: builds,
+        env: settings.env.clone(),
+        rustfmt_on_save: settings.rustfmt_on_save,
+        clippy: settings.clippy,
+        ignore: settings.ignore.clone(),
+        editor: settings.editor.clone()
+    };
+
+    let mut cache = state.project_settings.borrow_mut();
+    if !cache.contains_key(project_path) {
+        cache.insert(project_path.to_path_buf(), read_project_settings(project_path));
+    }
+    match cache.get(project_path) {
+        Some(&Some(ref local)) => merge_project_settings(&global, local),
+        _ => global
+    }
+}
+
 fn get_settings() -> Settings {
     Settings {
         keys: ::utils::KeySettings {
@@ -289,6 +736,8 @@ fn get_settings() -> Settings {
             import: Some("i".to_string()),
             rename: Some("n".to_string()),
             remove: Some("g".to_string()),
+            go_to_file: Some("o".to_string()),
+            find_in_files: Some("f".to_string()),
 
             run: Some("a".to_string()),
             build: Some("k".to_string()),
@@ -299,10 +748,27 @@ fn get_settings() -> Settings {
             save: Some("s".to_string()),
             undo: Some("z".to_string()),
             redo: Some("r".to_string()),
+            prev_error: Some("comma".to_string()),
+            next_error: Some("period".to_string()),
             font_dec: Some("minus".to_string()),
             font_inc: Some("equal".to_string()),
             close: Some("w".to_string())
-        }
+        },
+        stop_signal: Some("SIGINT".to_string()),
+        stop_grace_period: Some(3000),
+        builds: Some(BuildSettings {
+            run: get_command(&["cargo", "run"], true),
+            build: get_command(&["cargo", "build"], true),
+            test: get_command(&["cargo", "test"], true),
+            clean: get_command(&["cargo", "clean"], false),
+            profiles: Some(vec![get_profile("debug", false), get_profile("release", true)])
+        }),
+        project_builds: None,
+        env: None,
+        rustfmt_on_save: Some(false),
+        clippy: Some(false),
+        ignore: Some(vec!["target".to_string()]),
+        editor: None
     }
 }
 
@@ -362,6 +828,12 @@ pub fn read_settings() -> Settings {
             if let Some(key) = keys.remove {
                 settings.keys.remove = Some(settings.keys.remove.unwrap_or(key));
             }
+            if let Some(key) = keys.go_to_file {
+                settings.keys.go_to_file = Some(settings.keys.go_to_file.unwrap_or(key));
+            }
+            if let Some(key) = keys.find_in_files {
+                settings.keys.find_in_files = Some(settings.keys.find_in_files.unwrap_or(key));
+            }
 
             if let Some(key) = keys.run {
                 settings.keys.run = Some(settings.keys.run.unwrap_or(key));
@@ -388,6 +860,12 @@ pub fn read_settings() -> Settings {
             if let Some(key) = keys.redo {
                 settings.keys.redo = Some(settings.keys.redo.unwrap_or(key));
             }
+            if let Some(key) = keys.prev_error {
+                settings.keys.prev_error = Some(settings.keys.prev_error.unwrap_or(key));
+            }
+            if let Some(key) = keys.next_error {
+                settings.keys.next_error = Some(settings.keys.next_error.unwrap_or(key));
+            }
             if let Some(key) = keys.font_dec {
                 settings.keys.font_dec = Some(settings.keys.font_dec.unwrap_or(key));
             }
@@ -398,9 +876,151 @@ pub fn read_settings() -> Settings {
                 settings.keys.close = Some(settings.keys.close.unwrap_or(key));
             }
 
+            if let Some(signal) = default_settings.stop_signal {
+                settings.stop_signal = Some(settings.stop_signal.unwrap_or(signal));
+            }
+            if let Some(period) = default_settings.stop_grace_period {
+                settings.stop_grace_period = Some(settings.stop_grace_period.unwrap_or(period));
+            }
+
+            if let Some(rustfmt_on_save) = default_settings.rustfmt_on_save {
+                settings.rustfmt_on_save = Some(settings.rustfmt_on_save.unwrap_or(rustfmt_on_save));
+            }
+            if let Some(clippy) = default_settings.clippy {
+                settings.clippy = Some(settings.clippy.unwrap_or(clippy));
+            }
+            if let Some(ignore) = default_settings.ignore {
+                settings.ignore = Some(settings.ignore.unwrap_or(ignore));
+            }
+
+            if let Some(default_builds) = default_settings.builds {
+                settings.builds = Some(match settings.builds {
+                    Some(builds) => merge_build_settings(&default_builds, &builds),
+                    None => default_builds
+                });
+            }
+
             return settings;
         }
     }
 
     default_settings
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{BuildSettings, CommandSettings, EditorSettings, ProjectSettings};
+    use super::{merge_build_settings, merge_maps, merge_project_settings};
+    use std::collections::BTreeMap;
+
+    fn command(args: &[&str]) -> Option<CommandSettings> {
+        Some(CommandSettings {
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            env: None,
+            working_dir: None,
+            use_profile: None
+        })
+    }
+
+    fn builds(run: &[&str], build: Option<&[&str]>) -> BuildSettings {
+        BuildSettings {
+            run: command(run),
+            build: build.and_then(command),
+            test: None,
+            clean: None,
+            profiles: None
+        }
+    }
+
+    fn map(pairs: &[(&str, &str)]) -> Option<BTreeMap<String, String>> {
+        Some(pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    fn empty() -> ProjectSettings {
+        ProjectSettings {
+            builds: None,
+            env: None,
+            rustfmt_on_save: None,
+            clippy: None,
+            ignore: None,
+            editor: None
+        }
+    }
+
+    fn args(command: &Option<CommandSettings>) -> Option<Vec<String>> {
+        command.as_ref().map(|command| command.args.clone())
+    }
+
+    #[test]
+    fn build_overrides_win_and_missing_ones_fall_back() {
+        let base = builds(&["cargo", "run"], Some(&["cargo", "build"]));
+        let overrides = builds(&["cargo", "run", "--release"], None);
+        let merged = merge_build_settings(&base, &overrides);
+        assert_eq!(args(&merged.run), Some(vec!["cargo".to_string(), "run".to_string(), "--release".to_string()]));
+        assert_eq!(args(&merged.build), Some(vec!["cargo".to_string(), "build".to_string()]));
+        assert!(merged.test.is_none());
+    }
+
+    #[test]
+    fn maps_merge_key_by_key() {
+        let merged = merge_maps(&map(&[("A", "1"), ("B", "2")]), &map(&[("B", "3"), ("C", "4")]));
+        assert_eq!(merged, map(&[("A", "1"), ("B", "3"), ("C", "4")]));
+    }
+
+    #[test]
+    fn maps_use_whichever_one_is_set() {
+        assert_eq!(merge_maps(&map(&[("A", "1")]), &None), map(&[("A", "1")]));
+        assert_eq!(merge_maps(&None, &map(&[("B", "2")])), map(&[("B", "2")]));
+        assert_eq!(merge_maps(&None, &None), None);
+    }
+
+    #[test]
+    fn project_settings_override_global_ones() {
+        let mut base = empty();
+        base.clippy = Some(false);
+        base.rustfmt_on_save = Some(true);
+        base.env = map(&[("RUST_LOG", "info"), ("RUST_BACKTRACE", "1")]);
+        base.ignore = Some(vec!["target".to_string()]);
+        base.editor = Some(EditorSettings { tab_width: Some(4), expand_tab: Some(true), text_width: None });
+
+        let mut overrides = empty();
+        overrides.clippy = Some(true);
+        overrides.env = map(&[("RUST_LOG", "debug")]);
+        overrides.ignore = Some(vec!["target".to_string(), "*.log".to_string()]);
+        overrides.editor = Some(EditorSettings { tab_width: Some(2), expand_tab: None, text_width: Some(80) });
+
+        let merged = merge_project_settings(&base, &overrides);
+        assert_eq!(merged.clippy, Some(true));
+        assert_eq!(merged.rustfmt_on_save, Some(true));
+        assert_eq!(merged.env, map(&[("RUST_BACKTRACE", "1"), ("RUST_LOG", "debug")]));
+        assert_eq!(merged.ignore, Some(vec!["target".to_string(), "*.log".to_string()]));
+        let editor = merged.editor.unwrap();
+        assert_eq!((editor.tab_width, editor.expand_tab, editor.text_width), (Some(2), Some(true), Some(80)));
+    }
+
+    #[test]
+    fn none_never_overrides_some() {
+        let mut base = empty();
+        base.builds = Some(builds(&["cargo", "run"], None));
+        base.clippy = Some(true);
+        let merged = merge_project_settings(&base, &empty());
+        assert_eq!(merged.clippy, Some(true));
+        assert_eq!(args(&merged.builds.unwrap().run), Some(vec!["cargo".to_string(), "run".to_string()]));
+
+        let mut overrides = empty();
+        overrides.builds = Some(builds(&["make"], None));
+        let merged = merge_project_settings(&empty(), &overrides);
+        assert_eq!(args(&merged.builds.unwrap().run), Some(vec!["make".to_string()]));
+        assert!(merged.env.is_none());
+    }
+
+    #[test]
+    fn merging_is_deterministic() {
+        let base = map(&[("Z", "1"), ("A", "2"), ("M", "3")]);
+        let overrides = map(&[("M", "4"), ("B", "5")]);
+        let first: Vec<(String, String)> = merge_maps(&base, &overrides).unwrap().into_iter().collect();
+        let second: Vec<(String, String)> = merge_maps(&base, &overrides).unwrap().into_iter().collect();
+        assert_eq!(first, second);
+        assert_eq!(first[0], ("A".to_string(), "2".to_string()));
+    }
+}
@@ -0,0 +1,313 @@
+use libc::{c_char, c_int};
+use libc::consts::os::extra::O_NONBLOCK;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, PathExt};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender, TryRecvError};
+use std::thread;
+
+static IN_CLOSE_WRITE : u32 = 0x8;
+static IN_MOVED_FROM : u32 = 0x40;
+static IN_MOVED_TO : u32 = 0x80;
+static IN_CREATE : u32 = 0x100;
+static IN_DELETE : u32 = 0x200;
+static IN_DELETE_SELF : u32 = 0x400;
+static IN_Q_OVERFLOW : u32 = 0x4000;
+static IN_IGNORED : u32 = 0x8000;
+static IN_ISDIR : u32 = 0x40000000;
+
+static EVENT_HEADER_SIZE : usize = 16;
+static DEBOUNCE_MS : i32 = 100;
+// changes are sent at least this often, even if the events never stop
+static MAX_DELAY_MS : i64 = 1000;
+
+extern "C" {
+    fn inotify_init1 (flags: c_int) -> c_int;
+    fn inotify_add_watch (fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+    fn inotify_rm_watch (fd: c_int, wd: c_int) -> c_int;
+}
+
+pub enum Change {
+    Created(PathBuf),
+    Removed(PathBuf),
+    // a rename within the watched directories, which keeps expansions and selection
+    Moved(PathBuf, PathBuf),
+    // a file was written in place, which only matters for settings and ignore files
+    Modified(PathBuf),
+    // events were dropped, so everything needs to be re-read
+    Rescan
+}
+
+enum Command {
+    Watch(PathBuf, Vec<String>),
+    Unwatch(PathBuf),
+    WatchAll(bool)
+}
+
+pub struct Watcher {
+    sender: Sender<Command>
+}
+
+struct Watches {
+    fd: i32,
+    dirs: HashMap<i32, PathBuf>,
+    matchers: HashMap<PathBuf, ::ignore::Matcher>,
+    changes: Vec<Change>,
+    // the index of the Removed change for each move that hasn't seen its other half yet
+    moves: HashMap<u32, usize>,
+    batch_start: i64,
+    // hidden and ignored dirs are only watched while they're shown in the tree
+    watch_all: bool
+}
+
+impl Watches {
+    // nested projects use their own rules, so the closest root wins
+    fn is_ignored(&mut self, path: &Path) -> bool {
+        let root = self.matchers.keys()
+            .filter(|root| path.starts_with(root))
+            .max_by(|root| root.components().count())
+            .cloned();
+        match root.and_then(|root| self.matchers.get_mut(&root)) {
+            Some(matcher) => matcher.is_ignored(path, true),
+            None => false
+        }
+    }
+
+    fn push(&mut self, change: Change) {
+        if self.changes.is_empty() {
+            self.batch_start = ::ffi::now_ms();
+        }
+        self.changes.push(change);
+    }
+
+    // moves that didn't get paired by now stay as removals
+    fn take_changes(&mut self) -> Vec<Change> {
+        self.moves.clear();
+        mem::replace(&mut self.changes, Vec::new())
+    }
+
+    fn add(&mut self, path: &Path) {
+        if !self.watch_all {
+            if self.is_ignored(path) {
+                return;
+            }
+            if let Some(leaf_str) = path.file_name().and_then(|s| s.to_str()) {
+                if leaf_str.starts_with(".") {
+                    return;
+                }
+            }
+        }
+
+        if let Some(path_str) = path.to_str() {
+            let mask = IN_CREATE | IN_DELETE | IN_MOVED_FROM | IN_MOVED_TO | IN_DELETE_SELF | IN_CLOSE_WRITE;
+            let wd = add_inotify_watch(self.fd, path_str, mask);
+            // the dir may be gone already, or the watch limit reached, and then it just isn't watched
+            if wd < 0 {
+                return;
+            }
+            self.dirs.insert(wd, path.to_path_buf());
+        }
+
+        if let Ok(children) = fs::read_dir(path) {
+            for child in children {
+                if let Ok(dir_entry) = child {
+                    let child_path = dir_entry.path();
+                    if child_path.is_dir() {
+                        self.add(&child_path);
+                    }
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, root: &Path) {
+        self.remove_dir(root);
+        self.matchers.remove(root);
+    }
+
+    fn read_changes(&mut self) {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = ::ffi::read_bytes(self.fd, &mut buf);
+            if n <= 0 {
+                break;
+            }
+
+            let mut offset = 0;
+            while offset + EVENT_HEADER_SIZE <= n as usize {
+                let header = &buf[offset..offset + EVENT_HEADER_SIZE];
+                let wd = read_u32(&header[0..4]) as i32;
+                let mask = read_u32(&header[4..8]);
+                let cookie = read_u32(&header[8..12]);
+                let len = read_u32(&header[12..16]) as usize;
+                let name_bytes = &buf[offset + EVENT_HEADER_SIZE..offset + EVENT_HEADER_SIZE + len];
+                offset += EVENT_HEADER_SIZE + len;
+
+                if mask & IN_Q_OVERFLOW != 0 {
+                    self.push(Change::Rescan);
+                    continue;
+                }
+                if mask & (IN_IGNORED | IN_DELETE_SELF) != 0 {
+                    self.dirs.remove(&wd);
+                    continue;
+                }
+
+                // the name is padded with null bytes
+                let name_len = name_bytes.iter().position(|b| *b == 0).unwrap_or(len);
+                let name = String::from_utf8_lossy(&name_bytes[..name_len]).into_owned();
+                let path = match self.dirs.get(&wd) {
+                    Some(dir) => dir.join(&name),
+                    None => continue
+                };
+
+                if mask & (IN_CREATE | IN_MOVED_TO) != 0 {
+                    if mask & IN_ISDIR != 0 {
+                        self.add(&path);
+                    }
+                    // the two halves of a rename share a cookie
+                    let index = if mask & IN_MOVED_TO != 0 { self.moves.remove(&cookie) } else { None };
+                    let from = match index {
+                        Some(i) => match self.changes[i] {
+                            Change::Removed(ref from) => Some((i, from.clone())),
+                            _ => None
+                        },
+                        None => None
+                    };
+                    match from {
+                        Some((i, from)) => self.changes[i] = Change::Moved(from, path),
+                        None => self.push(Change::Created(path))
+                    }
+                } else if mask & (IN_DELETE | IN_MOVED_FROM) != 0 {
+                    if mask & IN_ISDIR != 0 {
+                        self.remove_dir(&path);
+                    }
+                    if mask & IN_MOVED_FROM != 0 {
+                        self.moves.insert(cookie, self.changes.len());
+                    }
+                    self.push(Change::Removed(path));
+                } else if mask & IN_CLOSE_WRITE != 0 && (::ignore::is_ignore_file(&path) || ::utils::is_settings_file(&path)) {
+                    self.push(Change::Modified(path));
+                }
+            }
+        }
+    }
+
+    fn remove_dir(&mut self, dir: &Path) {
+        let mut removed = Vec::new();
+        for (wd, path) in self.dirs.iter() {
+            if path.starts_with(dir) {
+                removed.push(*wd);
+            }
+        }
+        for wd in removed.iter() {
+            remove_inotify_watch(self.fd, *wd);
+            self.dirs.remove(wd);
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    // inotify uses the native byte order
+    let mut n = 0u32;
+    for (i, b) in bytes.iter().enumerate() {
+        if cfg!(target_endian = "little") {
+            n |= (*b as u32) << (i * 8);
+        } else {
+            n = (n << 8) | (*b as u32);
+        }
+    }
+    n
+}
+
+impl Watcher {
+    pub fn watch(&self, root: &Path, ignore: Vec<String>) {
+        self.sender.send(Command::Watch(root.to_path_buf(), ignore)).ok();
+    }
+
+    pub fn unwatch(&self, root: &Path) {
+        self.sender.send(Command::Unwatch(root.to_path_buf())).ok();
+    }
+
+    pub fn watch_all(&self, watch_all: bool) {
+        self.sender.send(Command::WatchAll(watch_all)).ok();
+    }
+}
+
+// watches the project directories in a background thread, sending changes in
+// batches once things have been quiet for a moment, or once they've waited long enough
+pub fn start(notifier: ::ffi::Notifier<Vec<Change>>) -> Watcher {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut watches = Watches {
+            fd: new_inotify(),
+            dirs: HashMap::new(),
+            matchers: HashMap::new(),
+            changes: Vec::new(),
+            moves: HashMap::new(),
+            batch_start: 0,
+            watch_all: false
+        };
+        // without inotify the tree is only updated when it's refreshed
+        if watches.fd < 0 {
+            return;
+        }
+
+        loop {
+            loop {
+                match receiver.try_recv() {
+                    Ok(Command::Watch(root, ignore)) => {
+                        watches.remove(&root);
+                        watches.matchers.insert(root.clone(), ::ignore::Matcher::new(&root, ignore.as_ref()));
+                        watches.add(&root);
+                    },
+                    Ok(Command::Unwatch(root)) => watches.remove(&root),
+                    Ok(Command::WatchAll(watch_all)) => {
+                        if watches.watch_all != watch_all {
+                            watches.watch_all = watch_all;
+                            let roots: Vec<PathBuf> = watches.matchers.keys().cloned().collect();
+                            for root in roots.iter() {
+                                watches.remove_dir(root);
+                                watches.add(root);
+                            }
+                        }
+                    },
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        ::ffi::close_fd(watches.fd);
+                        return;
+                    }
+                }
+            }
+
+            let is_readable = ::ffi::wait_readable(watches.fd, DEBOUNCE_MS);
+            if is_readable {
+                watches.read_changes();
+            }
+            if watches.changes.len() > 0 &&
+                (!is_readable || ::ffi::now_ms() - watches.batch_start >= MAX_DELAY_MS)
+            {
+                notifier.send(watches.take_changes());
+            }
+        }
+    });
+
+    Watcher { sender: sender }
+}
+
+fn new_inotify() -> c_int {
+    unsafe { inotify_init1(O_NONBLOCK) }
+}
+
+fn add_inotify_watch(fd: c_int, path_str: &str, mask: u32) -> c_int {
+    match CString::new(path_str) {
+        Ok(c_path) => unsafe { inotify_add_watch(fd, c_path.as_ptr(), mask) },
+        Err(_) => -1
+    }
+}
+
+fn remove_inotify_watch(fd: c_int, wd: c_int) {
+    unsafe { inotify_rm_watch(fd, wd) };
+}